@@ -0,0 +1,77 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use chatty::http::{parse_request, scan_head};
+
+/// Representative requests a chat server handles: a bare GET, a GET from a browser with a
+/// typical set of headers and a POST of a message.
+const REQUESTS: &[(&str, &str)] = &[
+    ("get", "GET /chats/34/messages?limit=20 HTTP/1.1\r\nHost: www.example.com\r\n\r\n"),
+    (
+        "get_with_headers",
+        "GET /chats/34/messages HTTP/1.1\r\n\
+         Host: www.example.com\r\n\
+         User-Agent: Mozilla/5.0 (X11; Ubuntu; Linux x86_64; rv:69.0) Gecko/20100101 Firefox/69.0\r\n\
+         Accept: application/json\r\n\
+         Accept-Language: en-US\r\n\
+         Accept-Encoding: gzip, deflate\r\n\
+         Connection: keep-alive\r\n\
+         \r\n",
+    ),
+    (
+        "post",
+        "POST /messages HTTP/1.1\r\n\
+         Host: www.example.com\r\n\
+         Content-Type: application/json\r\n\
+         Content-Length: 97\r\n\
+         \r\n\
+         {\"timestamp\": 1572297339000, \"message\": \"Hello\", \"sourceUserId\": 9837, \"destinationUserId\": 1983}",
+    ),
+];
+
+/// The multi-pass search for the request line and the end of the head that `scan_head()`
+/// replaced, kept as the baseline it's measured against.
+fn multi_pass_head(request: &str) -> (&str, &str, &str)
+{
+    let request_line = request.lines().next().unwrap_or("");
+    let mut line_start = 0;
+
+    while let Some(offset) = request[line_start ..].find('\n')
+    {
+        let line_end = line_start + offset;
+        if request[line_start .. line_end].trim().is_empty()
+        {
+            return (request_line, &request[.. line_start], &request[line_end + 1 ..]);
+        }
+
+        line_start = line_end + 1;
+    }
+
+    (request_line, request, &request[request.len() ..])
+}
+
+/// Compare finding the head in a single pass against the multi-pass baseline.
+fn bench_scan_head(c: &mut Criterion)
+{
+    let mut group = c.benchmark_group("scan_head");
+    for (name, request) in REQUESTS
+    {
+        assert_eq!(scan_head(request), multi_pass_head(request));
+        group.bench_function(format!("single_pass/{}", name), |b| b.iter(|| scan_head(black_box(request))));
+        group.bench_function(format!("multi_pass/{}", name), |b| b.iter(|| multi_pass_head(black_box(request))));
+    }
+    group.finish();
+}
+
+/// Measure parsing whole requests.
+fn bench_parse_request(c: &mut Criterion)
+{
+    let mut group = c.benchmark_group("parse_request");
+    for (name, request) in REQUESTS
+    {
+        group.bench_function(*name, |b| b.iter(|| parse_request(black_box(request)).unwrap()));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_scan_head, bench_parse_request);
+criterion_main!(benches);
\ No newline at end of file
@@ -0,0 +1,34 @@
+#![no_main]
+
+use chatty::http::{self, ParseOptions};
+use libfuzzer_sys::fuzz_target;
+
+// Run with `cargo +nightly fuzz run parse_request`. Parsing must fail with a `ParseError` rather
+// than panic, whatever the input, so any panic is a bug.
+fuzz_target!(|data: &[u8]| {
+    let _ = http::parse_request_from_reader(data);
+
+    let lenient = ParseOptions
+    {
+        lenient_method_case: true,
+        allow_missing_trailing_crlf: true,
+        allow_bare_lf: true,
+        ..ParseOptions::default()
+    };
+
+    for options in [ParseOptions::default(), lenient].iter()
+    {
+        if let Ok(request) = http::parse_request_with(data, options)
+        {
+            // The accessors that parse headers on demand mustn't panic either.
+            let _ = request.host();
+            let _ = request.authorization();
+            let _ = request.cookies();
+            let _ = request.accept_quality("application/json");
+            let _ = request.parse_form_body();
+            let _ = request.parse_multipart();
+            let _ = http::parse_request(&request.to_bytes());
+        }
+    }
+    let _ = http::parse_requests(data);
+});
\ No newline at end of file
@@ -0,0 +1,9 @@
+pub mod http;
+pub mod middleware;
+pub mod models;
+pub mod multipart;
+pub mod response;
+pub mod router;
+pub mod server;
+pub mod store;
+pub mod websocket;
\ No newline at end of file
@@ -1,6 +1,3 @@
-mod http;
-mod models;
-
 fn main()
 {
 
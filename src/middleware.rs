@@ -0,0 +1,577 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::http::{HttpRequest, Method};
+use crate::response::{HttpResponse, StatusCode};
+
+/// A layer wrapped around a server's handler for concerns that apply to every request, such as
+/// authentication, logging or rate limiting.
+pub trait Middleware: Send + Sync
+{
+    /// Handle a request on its way to the handler.
+    ///
+    /// # Parameters
+    ///
+    /// - `request`: a reference to the request being handled.
+    /// - `next`: the rest of the chain, i.e. the next middleware or, at the end of the chain, the
+    ///   handler. Not calling it short-circuits the chain, e.g. to reject a request.
+    ///
+    /// # Returns
+    ///
+    /// The response to the request, which is usually the one returned by `next`, possibly
+    /// modified on its way out.
+    fn handle(&self, request: &HttpRequest, next: &dyn Fn(&HttpRequest) -> HttpResponse) -> HttpResponse;
+}
+
+/// Pass a request through a chain of middleware to a handler.
+///
+/// # Parameters
+///
+/// - `middleware`: the chain of middleware, outermost first. The first middleware sees the
+///   request first and the response last.
+/// - `handler`: the handler at the end of the chain.
+/// - `request`: a reference to the request to handle.
+///
+/// # Returns
+///
+/// The response produced by the chain.
+pub fn chain<H>(middleware: &[Box<dyn Middleware>], handler: &H, request: &HttpRequest) -> HttpResponse
+where
+    H: Fn(&HttpRequest) -> HttpResponse,
+{
+    match middleware.split_first()
+    {
+        Some((first, rest)) => first.handle(request, &|request| chain(rest, handler, request)),
+        None => handler(request),
+    }
+}
+
+/// Middleware that logs every request along with the status of its response, e.g.
+/// `GET /chats HTTP/1.1 -> 200`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Logger;
+
+impl Middleware for Logger
+{
+    fn handle(&self, request: &HttpRequest, next: &dyn Fn(&HttpRequest) -> HttpResponse) -> HttpResponse
+    {
+        let response = next(request);
+        log::info!("{} -> {}", request, response.status().code());
+        response
+    }
+}
+
+/// How often a `RateLimiter` evicts the buckets that have refilled to their capacity, which are
+/// no different from the full bucket a new client starts with.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Middleware that limits how often each client can make requests, using a token bucket per
+/// client IP address. Every request takes a token from its client's bucket, which refills at a
+/// steady rate up to its capacity. A client whose bucket is empty is answered with a
+/// `429 Too Many Requests` whose `Retry-After` header says how many seconds to wait. Requests
+/// without a client address aren't limited.
+#[derive(Debug)]
+pub struct RateLimiter
+{
+    capacity: f64,
+    refill_per_second: f64,
+    buckets: Mutex<Buckets>,
+}
+
+/// The buckets of the clients a `RateLimiter` has seen, and when the full ones were last
+/// evicted.
+#[derive(Debug)]
+struct Buckets
+{
+    clients: HashMap<IpAddr, Bucket>,
+    swept: Instant,
+}
+
+/// The tokens left in a client's bucket as of the last time it was updated.
+#[derive(Debug)]
+struct Bucket
+{
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter
+{
+    /// Create a rate limiter that every client starts with a full bucket of.
+    ///
+    /// # Parameters
+    ///
+    /// - `capacity`: the number of requests a client can burst before being limited.
+    /// - `refill_per_second`: the number of requests per second a client can sustain. A rate
+    ///   that isn't positive, including `NaN`, never refills a bucket, so each client can only
+    ///   make `capacity` requests, and an infinite rate never limits a client.
+    pub fn new(capacity: u32, refill_per_second: f64) -> RateLimiter
+    {
+        RateLimiter
+        {
+            capacity: f64::from(capacity),
+            refill_per_second: if refill_per_second > 0.0 { refill_per_second } else { 0.0 },
+            buckets: Mutex::new(
+                Buckets
+                {
+                    clients: HashMap::new(),
+                    swept: Instant::now(),
+                }
+            ),
+        }
+    }
+
+    /// Take a token from a client's bucket, refilling it for the time since it was last used.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The client had a token to take.
+    /// - `Err`: How long until the client's bucket has a token again, or `None` if it never
+    ///   will.
+    fn acquire(&self, client: IpAddr, now: Instant) -> Result<(), Option<Duration>>
+    {
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        if now.saturating_duration_since(buckets.swept) >= SWEEP_INTERVAL
+        {
+            buckets.clients.retain(|_, bucket| self.refill(bucket, now) < self.capacity);
+            buckets.swept = now;
+        }
+
+        let bucket = buckets.clients.entry(client).or_insert(Bucket { tokens: self.capacity, updated: now });
+        bucket.tokens = self.refill(bucket, now);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0
+        {
+            bucket.tokens -= 1.0;
+            Ok(())
+        }
+        else if self.refill_per_second > 0.0
+        {
+            Err(Some(Duration::from_secs_f64((1.0 - bucket.tokens) / self.refill_per_second)))
+        }
+        else
+        {
+            Err(None)
+        }
+    }
+
+    /// Returns the tokens a bucket has at `now`, after refilling it for the time since it was
+    /// last updated.
+    fn refill(&self, bucket: &Bucket, now: Instant) -> f64
+    {
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        // With an infinite rate, no time elapsing would make the tokens NaN, which `min()` ignores.
+        (bucket.tokens + elapsed * self.refill_per_second).min(self.capacity)
+    }
+}
+
+impl Middleware for RateLimiter
+{
+    fn handle(&self, request: &HttpRequest, next: &dyn Fn(&HttpRequest) -> HttpResponse) -> HttpResponse
+    {
+        let client = match request.peer_addr()
+        {
+            Some(peer_addr) => peer_addr.ip(),
+            None => return next(request),
+        };
+
+        match self.acquire(client, Instant::now())
+        {
+            Ok(()) => next(request),
+            Err(wait) => {
+                let mut response = HttpResponse::builder().status(StatusCode::TooManyRequests);
+                // Retry-After is in whole seconds, so round up rather than invite an early retry.
+                // A client whose bucket never refills isn't told to retry at all.
+                if let Some(wait) = wait
+                {
+                    let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+                    response = response.header("Retry-After", &retry_after.to_string());
+                }
+                response.build()
+            },
+        }
+    }
+}
+
+/// Middleware that lets browser clients on other origins make requests, using CORS. Preflight
+/// requests, i.e. `OPTIONS` requests with an `Access-Control-Request-Method` header, are answered
+/// with a `204 No Content` listing the allowed methods and headers without reaching the handler.
+/// Other responses to an allowed origin get an `Access-Control-Allow-Origin` header. Requests
+/// from origins that aren't allowed are answered without any `Access-Control-Allow-*` headers,
+/// which browsers treat as a refusal.
+#[derive(Debug, Clone)]
+pub struct Cors
+{
+    allowed_origins: Vec<String>,
+    allowed_methods: Vec<Method>,
+    allowed_headers: Vec<String>,
+}
+
+impl Default for Cors
+{
+    fn default() -> Cors
+    {
+        Cors
+        {
+            allowed_origins: Vec::new(),
+            allowed_methods: vec![Method::Get, Method::Head, Method::Post],
+            allowed_headers: Vec::new(),
+        }
+    }
+}
+
+impl Cors
+{
+    /// Create a CORS layer that doesn't allow any origin yet. The methods allowed are GET, HEAD
+    /// and POST, and no headers beyond the ones browsers always allow.
+    pub fn new() -> Cors
+    {
+        Cors::default()
+    }
+
+    /// Allow requests from an origin, e.g. `"https://chat.example.com"`, or from any origin with
+    /// `"*"`.
+    pub fn allow_origin(mut self, origin: &str) -> Cors
+    {
+        self.allowed_origins.push(String::from(origin));
+        self
+    }
+
+    /// Set the methods preflight requests are allowed to ask for.
+    pub fn allow_methods(mut self, methods: &[Method]) -> Cors
+    {
+        self.allowed_methods = methods.to_vec();
+        self
+    }
+
+    /// Set the request headers preflight requests are allowed to ask for, e.g. `"Content-Type"`.
+    pub fn allow_headers(mut self, headers: &[&str]) -> Cors
+    {
+        self.allowed_headers = headers.iter().map(|&header| String::from(header)).collect();
+        self
+    }
+
+    /// Returns the value of the `Access-Control-Allow-Origin` header for a request from an
+    /// origin, or `None` if the origin isn't allowed.
+    fn allow_origin_header<'a>(&self, origin: &'a str) -> Option<&'a str>
+    {
+        if self.allowed_origins.iter().any(|allowed| allowed == "*")
+        {
+            Some("*")
+        }
+        else if self.allowed_origins.iter().any(|allowed| allowed == origin)
+        {
+            Some(origin)
+        }
+        else
+        {
+            None
+        }
+    }
+}
+
+impl Middleware for Cors
+{
+    fn handle(&self, request: &HttpRequest, next: &dyn Fn(&HttpRequest) -> HttpResponse) -> HttpResponse
+    {
+        let origin = match request.header("Origin")
+        {
+            Some(origin) => origin,
+            None => return next(request),
+        };
+        let allow_origin = self.allow_origin_header(origin);
+        let preflight = request.method() == Method::Options && request.header("Access-Control-Request-Method").is_some();
+
+        let mut response = if preflight
+        {
+            let mut response = HttpResponse::new(StatusCode::NoContent);
+            if allow_origin.is_some()
+            {
+                let methods: Vec<&str> = self.allowed_methods.iter().map(Method::as_str).collect();
+                response.add_header("Access-Control-Allow-Methods", &methods.join(", "));
+                if !self.allowed_headers.is_empty()
+                {
+                    response.add_header("Access-Control-Allow-Headers", &self.allowed_headers.join(", "));
+                }
+            }
+            response
+        }
+        else
+        {
+            next(request)
+        };
+
+        if let Some(allow_origin) = allow_origin
+        {
+            response.add_header("Access-Control-Allow-Origin", allow_origin);
+        }
+        // Unless every origin gets the same answer, caches must keep the answers apart.
+        if allow_origin != Some("*")
+        {
+            response.add_header("Vary", "Origin");
+        }
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::http::parse_request;
+
+    /// Middleware that rejects requests without an `Authorization` header.
+    struct RequireAuthorization;
+
+    impl Middleware for RequireAuthorization
+    {
+        fn handle(&self, request: &HttpRequest, next: &dyn Fn(&HttpRequest) -> HttpResponse) -> HttpResponse
+        {
+            match request.header("Authorization")
+            {
+                Some(_) => next(request),
+                None => HttpResponse::new(StatusCode::Unauthorized),
+            }
+        }
+    }
+
+    /// Middleware that adds a header to every response.
+    struct AddHeader(&'static str);
+
+    impl Middleware for AddHeader
+    {
+        fn handle(&self, request: &HttpRequest, next: &dyn Fn(&HttpRequest) -> HttpResponse) -> HttpResponse
+        {
+            let mut response = next(request);
+            response.add_header("X-Middleware", self.0);
+            response
+        }
+    }
+
+    /// Verify that the `chain()` function passes requests through every middleware to the
+    /// handler, and the responses back out in reverse order.
+    #[test]
+    fn test_chain()
+    {
+        let middleware: Vec<Box<dyn Middleware>> = vec![Box::new(Logger), Box::new(AddHeader("outer")), Box::new(AddHeader("inner"))];
+        let request = parse_request("GET /chats HTTP/1.1\r\n").unwrap();
+        let response = chain(&middleware, &|_: &HttpRequest| HttpResponse::ok("all chats"), &request);
+
+        assert_eq!(response.body(), Some(&b"all chats"[..]));
+        let added: Vec<&str> = response.headers().iter().map(|(_, value)| value.as_str()).collect();
+        assert_eq!(added, vec!["inner", "outer"]);
+
+        // Test that a chain without middleware just calls the handler.
+        let response = chain(&[], &|_: &HttpRequest| HttpResponse::not_found(), &request);
+        assert_eq!(response, HttpResponse::not_found());
+    }
+
+    /// Verify that a middleware that short-circuits the chain prevents the handler from running.
+    #[test]
+    fn test_chain_short_circuit()
+    {
+        let calls = AtomicUsize::new(0);
+        let handler = |_: &HttpRequest| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            HttpResponse::ok("all chats")
+        };
+        let middleware: Vec<Box<dyn Middleware>> = vec![Box::new(AddHeader("outer")), Box::new(RequireAuthorization)];
+
+        let mut request = parse_request("GET /chats HTTP/1.1\r\n").unwrap();
+        let mut response = chain(&middleware, &handler, &request);
+        assert_eq!(response.status(), StatusCode::Unauthorized);
+        assert_eq!(response.header("X-Middleware"), Some("outer"));
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        request = parse_request("GET /chats HTTP/1.1\r\nAuthorization: Bearer abc\r\n\r\n").unwrap();
+        response = chain(&middleware, &handler, &request);
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    /// Verify that the `RateLimiter` middleware answers a burst beyond its capacity with
+    /// `429 Too Many Requests`.
+    #[test]
+    fn test_rate_limiter()
+    {
+        let limiter: Vec<Box<dyn Middleware>> = vec![Box::new(RateLimiter::new(2, 0.5))];
+        let handler = |_: &HttpRequest| HttpResponse::ok("all chats");
+        let mut request = parse_request("GET /chats HTTP/1.1\r\n").unwrap();
+        request.set_peer_addr("10.0.0.1:50000".parse().unwrap());
+
+        assert_eq!(chain(&limiter, &handler, &request).status(), StatusCode::Ok);
+        assert_eq!(chain(&limiter, &handler, &request).status(), StatusCode::Ok);
+        let response = chain(&limiter, &handler, &request);
+        assert_eq!(response.status(), StatusCode::TooManyRequests);
+        assert_eq!(response.header("Retry-After"), Some("2"));
+
+        // Test that other clients have buckets of their own, even from the same port.
+        request.set_peer_addr("10.0.0.2:50000".parse().unwrap());
+        assert_eq!(chain(&limiter, &handler, &request).status(), StatusCode::Ok);
+
+        // Test that requests without a client address aren't limited.
+        let request = parse_request("GET /chats HTTP/1.1\r\n").unwrap();
+        for _ in 0 .. 5
+        {
+            assert_eq!(chain(&limiter, &handler, &request).status(), StatusCode::Ok);
+        }
+    }
+
+    /// Verify that the buckets of a `RateLimiter` refill over time, up to their capacity.
+    #[test]
+    fn test_rate_limiter_refill()
+    {
+        let limiter = RateLimiter::new(2, 4.0);
+        let client: IpAddr = "10.0.0.1".parse().unwrap();
+        let start = Instant::now();
+
+        assert_eq!(limiter.acquire(client, start), Ok(()));
+        assert_eq!(limiter.acquire(client, start), Ok(()));
+        assert_eq!(limiter.acquire(client, start), Err(Some(Duration::from_millis(250))));
+
+        // Test that a token is refilled after a quarter of a second.
+        let mut now = start + Duration::from_millis(250);
+        assert_eq!(limiter.acquire(client, now), Ok(()));
+        assert!(limiter.acquire(client, now).is_err());
+
+        // Test that an idle client's bucket doesn't fill beyond its capacity.
+        now += Duration::from_secs(60);
+        assert_eq!(limiter.acquire(client, now), Ok(()));
+        assert_eq!(limiter.acquire(client, now), Ok(()));
+        assert!(limiter.acquire(client, now).is_err());
+    }
+
+    /// Verify that a `RateLimiter` whose rate isn't positive never refills a bucket, and that an
+    /// infinite rate never limits a client.
+    #[test]
+    fn test_rate_limiter_invalid_rate()
+    {
+        let client: IpAddr = "10.0.0.1".parse().unwrap();
+        let start = Instant::now();
+        for rate in [0.0, -1.0, f64::NAN].iter()
+        {
+            let limiter = RateLimiter::new(1, *rate);
+            assert_eq!(limiter.acquire(client, start), Ok(()), "{}", rate);
+            assert_eq!(limiter.acquire(client, start + Duration::from_secs(3600)), Err(None), "{}", rate);
+        }
+
+        let limiter = RateLimiter::new(1, f64::INFINITY);
+        for _ in 0 .. 3
+        {
+            assert_eq!(limiter.acquire(client, start), Ok(()));
+        }
+
+        // Test that a client whose bucket never refills isn't told when to retry.
+        let limiter: Vec<Box<dyn Middleware>> = vec![Box::new(RateLimiter::new(0, 0.0))];
+        let handler = |_: &HttpRequest| HttpResponse::ok("all chats");
+        let mut request = parse_request("GET /chats HTTP/1.1\r\n").unwrap();
+        request.set_peer_addr("10.0.0.1:50000".parse().unwrap());
+        let response = chain(&limiter, &handler, &request);
+        assert_eq!(response.status(), StatusCode::TooManyRequests);
+        assert_eq!(response.header("Retry-After"), None);
+    }
+
+    /// Verify that a `RateLimiter` evicts the buckets of clients that have refilled to their
+    /// capacity, but keeps those that haven't.
+    #[test]
+    fn test_rate_limiter_eviction()
+    {
+        let limiter = RateLimiter::new(2, 0.01);
+        let start = Instant::now();
+        let clients = |limiter: &RateLimiter| limiter.buckets.lock().unwrap().clients.len();
+
+        for i in 0 .. 10
+        {
+            assert_eq!(limiter.acquire(IpAddr::from([10, 0, 0, i]), start), Ok(()));
+        }
+        assert_eq!(limiter.acquire(IpAddr::from([10, 0, 1, 0]), start + Duration::from_secs(50)), Ok(()));
+        assert_eq!(clients(&limiter), 11);
+
+        // Test that only the buckets that have had time to refill are evicted.
+        let now = start + Duration::from_secs(120);
+        assert_eq!(limiter.acquire(IpAddr::from([10, 0, 2, 0]), now), Ok(()));
+        assert_eq!(clients(&limiter), 2);
+
+        // Test that a client that was evicted starts over with a full bucket.
+        assert_eq!(limiter.acquire(IpAddr::from([10, 0, 0, 0]), now), Ok(()));
+        assert_eq!(limiter.acquire(IpAddr::from([10, 0, 0, 0]), now), Ok(()));
+        assert!(limiter.acquire(IpAddr::from([10, 0, 0, 0]), now).is_err());
+    }
+
+    /// Verify that the `Cors` middleware answers preflight requests from allowed origins with
+    /// the allowed methods and headers.
+    #[test]
+    fn test_cors_preflight()
+    {
+        let cors: Vec<Box<dyn Middleware>> = vec![Box::new(
+            Cors::new()
+                .allow_origin("https://chat.example.com")
+                .allow_methods(&[Method::Get, Method::Post, Method::Delete])
+                .allow_headers(&["Content-Type", "Authorization"])
+        )];
+        let handler = |_: &HttpRequest| HttpResponse::ok("handled");
+
+        let mut request = parse_request(
+            "OPTIONS /chats HTTP/1.1\r\nOrigin: https://chat.example.com\r\nAccess-Control-Request-Method: POST\r\n\r\n"
+        ).unwrap();
+        let mut response = chain(&cors, &handler, &request);
+        assert_eq!(response.status(), StatusCode::NoContent);
+        assert_eq!(response.body(), None);
+        assert_eq!(response.headers(), &[
+            (String::from("Access-Control-Allow-Methods"), String::from("GET, POST, DELETE")),
+            (String::from("Access-Control-Allow-Headers"), String::from("Content-Type, Authorization")),
+            (String::from("Access-Control-Allow-Origin"), String::from("https://chat.example.com")),
+            (String::from("Vary"), String::from("Origin")),
+        ]);
+
+        // Test that a disallowed origin isn't allowed anything.
+        request = parse_request(
+            "OPTIONS /chats HTTP/1.1\r\nOrigin: https://evil.example.com\r\nAccess-Control-Request-Method: POST\r\n\r\n"
+        ).unwrap();
+        response = chain(&cors, &handler, &request);
+        assert_eq!(response.status(), StatusCode::NoContent);
+        assert_eq!(response.headers(), &[(String::from("Vary"), String::from("Origin"))]);
+
+        // Test that an OPTIONS request that isn't a preflight reaches the handler.
+        request = parse_request("OPTIONS /chats HTTP/1.1\r\nOrigin: https://chat.example.com\r\n\r\n").unwrap();
+        assert_eq!(chain(&cors, &handler, &request).body(), Some(&b"handled"[..]));
+    }
+
+    /// Verify that the `Cors` middleware adds `Access-Control-Allow-Origin` to the responses to
+    /// requests from allowed origins.
+    #[test]
+    fn test_cors_actual_request()
+    {
+        let handler = |_: &HttpRequest| HttpResponse::ok("handled");
+        let mut cors: Vec<Box<dyn Middleware>> = vec![Box::new(Cors::new().allow_origin("https://chat.example.com"))];
+
+        let mut request = parse_request("GET /chats HTTP/1.1\r\nOrigin: https://chat.example.com\r\n\r\n").unwrap();
+        let mut response = chain(&cors, &handler, &request);
+        assert_eq!(response.body(), Some(&b"handled"[..]));
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("https://chat.example.com"));
+
+        request = parse_request("GET /chats HTTP/1.1\r\nOrigin: https://evil.example.com\r\n\r\n").unwrap();
+        response = chain(&cors, &handler, &request);
+        assert_eq!(response.body(), Some(&b"handled"[..]));
+        assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+
+        // Test that a request without an origin isn't a CORS request.
+        request = parse_request("GET /chats HTTP/1.1\r\n").unwrap();
+        assert_eq!(chain(&cors, &handler, &request), handler(&request));
+
+        // Test that the wildcard allows every origin.
+        cors = vec![Box::new(Cors::new().allow_origin("*"))];
+        request = parse_request("GET /chats HTTP/1.1\r\nOrigin: https://evil.example.com\r\n\r\n").unwrap();
+        response = chain(&cors, &handler, &request);
+        assert_eq!(response.header("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(response.header("Vary"), None);
+    }
+}
\ No newline at end of file
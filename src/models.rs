@@ -1,38 +1,415 @@
-#![allow(non_snake_case)]
+use std::borrow::Cow;
 use std::error::Error;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+use serde::de::Error as _;
 use serde::{Deserialize, Serialize};
-use serde_json::Result;
+use uuid::Uuid;
+
+/// The default maximum number of characters in a message's text.
+pub const DEFAULT_MAX_MESSAGE_LENGTH: usize = 4096;
+
+/// How fields of a JSON body that don't belong to the model being parsed are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownFields
+{
+    /// Ignore unknown fields, so clients can send extra metadata.
+    Allow,
+    /// Reject unknown fields, so a misspelled field is an error rather than a missing value.
+    Deny,
+}
+
+/// The errors that can occur while validating a model.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ModelError
+{
+    /// A chat's participant ids are the same user or include the reserved id `0`.
+    InvalidParticipants,
+    /// A message's text is longer than the maximum number of characters.
+    MessageTooLong { length: usize, max_length: usize },
+    /// The JSON a model was parsed from is missing a required field. Holds the name of the field.
+    MissingField(String),
+    /// The request a model was to be parsed from doesn't have a body.
+    MissingBody,
+    /// The body of the request a model was to be parsed from isn't of the media type the model
+    /// is parsed from, e.g. JSON, according to its `Content-Type`.
+    UnsupportedMediaType,
+    /// A field of the JSON a model was parsed from has the wrong type, e.g. a string where a
+    /// number is expected. Holds the description of the `serde_json` error.
+    InvalidType(String),
+    /// The JSON a model was parsed from is malformed or doesn't describe the model. Holds the
+    /// description of the `serde_json` error.
+    InvalidJson(String),
+    /// The form a model was parsed from is malformed, e.g. a field isn't properly
+    /// percent-encoded or a multipart body isn't terminated by its boundary.
+    InvalidForm,
+    /// The body a model was to be parsed from has to be text but isn't valid UTF-8. Holds the
+    /// offset of the first invalid byte from the start of the body.
+    InvalidUtf8 { offset: usize },
+}
+
+impl ModelError
+{
+    /// Returns a stable, machine-readable code for the kind of error, e.g. `"message_too_long"`,
+    /// for API consumers to match on rather than the description.
+    pub fn code(&self) -> &'static str
+    {
+        match self
+        {
+            ModelError::InvalidParticipants => "invalid_participants",
+            ModelError::MessageTooLong { .. } => "message_too_long",
+            ModelError::MissingField(_) => "missing_field",
+            ModelError::MissingBody => "missing_body",
+            ModelError::UnsupportedMediaType => "unsupported_media_type",
+            ModelError::InvalidType(_) => "invalid_type",
+            ModelError::InvalidJson(_) => "invalid_json",
+            ModelError::InvalidForm => "invalid_form",
+            ModelError::InvalidUtf8 { .. } => "invalid_utf8",
+        }
+    }
+}
+
+impl From<serde_json::Error> for ModelError
+{
+    fn from(error: serde_json::Error) -> ModelError
+    {
+        let description = error.to_string();
+        // serde only describes a missing field in its message, e.g. "missing field `message`",
+        // and likewise a type mismatch, e.g. "invalid type: string \"34\", expected u32".
+        let missing_field = description.strip_prefix("missing field `")
+            .and_then(|rest| rest.split_once('`'))
+            .map(|(field, _)| String::from(field));
+
+        match missing_field
+        {
+            Some(field) => ModelError::MissingField(field),
+            None if description.starts_with("invalid type: ") => ModelError::InvalidType(description),
+            None => ModelError::InvalidJson(description),
+        }
+    }
+}
+
+impl fmt::Display for ModelError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            ModelError::InvalidParticipants => {
+                write!(f, "A chat must be between two different users with non-zero ids!")
+            },
+            ModelError::MessageTooLong { length, max_length } => {
+                write!(f, "A message can't be longer than {} characters, got {}!", max_length, length)
+            },
+            ModelError::MissingField(field) => write!(f, "Missing field `{}`!", field),
+            ModelError::MissingBody => write!(f, "The request doesn't have a body!"),
+            ModelError::UnsupportedMediaType => write!(f, "The request's body isn't of a supported media type!"),
+            ModelError::InvalidType(error) | ModelError::InvalidJson(error) => write!(f, "Invalid JSON: {}", error),
+            ModelError::InvalidForm => write!(f, "The form is malformed!"),
+            ModelError::InvalidUtf8 { offset } => write!(f, "The body isn't valid UTF-8 at byte {}!", offset),
+        }
+    }
+}
+
+impl Error for ModelError {}
+
+/// The names of the fields of a `Chat` in its JSON representation.
+const CHAT_FIELDS: &[&str] = &["id", "participantIds"];
 
 /// # Chat Struct
 ///
-/// Struct that represents a chat session between two users
+/// Struct that represents a chat session between two users. The fields are camelCase in JSON,
+/// e.g. `participantIds`.
 /// `id`: The Chat's ID
-/// `participants_ids`: The unique ids of the two participants.
-#[derive(Serialize, Deserialize)]
+/// `participant_ids`: The unique ids of the two participants.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Chat
 {
     #[serde(default)]
     pub id: Option<u32>,
-    pub participantIds: [u32; 2],
+    pub participant_ids: [u32; 2],
+}
+
+impl Chat
+{
+    /// Parse a chat from JSON and validate it, rejecting unknown fields. See `parse_chat_with()`
+    /// and `validate()`.
+    ///
+    /// # Parameters
+    ///
+    /// - `json`: a reference to the `str` of the JSON to parse the chat from.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The valid `Chat`.
+    /// - `Err`: `ModelError::MissingField` or `ModelError::InvalidType` if a field is missing or
+    ///   of the wrong type, `ModelError::InvalidJson` if the JSON doesn't otherwise describe a
+    ///   chat, or the `ModelError` of the validation that failed.
+    pub fn from_json(json: &str) -> Result<Chat, ModelError>
+    {
+        let chat = parse_chat_with(json, UnknownFields::Deny)?;
+        chat.validate()?;
+
+        Ok(chat)
+    }
+
+    /// Validate that the chat is between two different users. The user id `0` is reserved and
+    /// can't participate in a chat.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The chat is valid.
+    /// - `Err`: `ModelError::InvalidParticipants` if the participants are the same user or
+    ///   either participant has the reserved id.
+    pub fn validate(&self) -> Result<(), ModelError>
+    {
+        let [first, second] = self.participant_ids;
+        if first == second || first == 0 || second == 0
+        {
+            return Err(ModelError::InvalidParticipants);
+        }
+
+        Ok(())
+    }
+}
+
+/// A point in time in milliseconds since the Unix epoch, which is how it's written in JSON,
+/// e.g. `1572297339000`. Construct it from seconds with `from_secs()` rather than wrapping a
+/// number of unknown units.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Timestamp(u64);
+
+impl Timestamp
+{
+    /// Create a timestamp from milliseconds since the Unix epoch.
+    pub fn from_millis(millis: u64) -> Timestamp
+    {
+        Timestamp(millis)
+    }
+
+    /// Create a timestamp from seconds since the Unix epoch. Times too far in the future to be
+    /// represented in milliseconds are clamped.
+    pub fn from_secs(secs: u64) -> Timestamp
+    {
+        Timestamp(secs.saturating_mul(1000))
+    }
+
+    /// Returns the current time.
+    pub fn now() -> Timestamp
+    {
+        // The system clock is never set before the epoch in practice, so that's treated as the epoch.
+        let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Timestamp(elapsed.as_millis() as u64)
+    }
+
+    /// Returns the number of milliseconds since the Unix epoch.
+    pub fn as_millis(&self) -> u64
+    {
+        self.0
+    }
+
+    /// Returns the number of whole seconds since the Unix epoch.
+    pub fn as_secs(&self) -> u64
+    {
+        self.0 / 1000
+    }
 }
 
 /// # Message Struct
 ///
-/// Struct that represents a message sent via a chat session between two users.
+/// Struct that represents a message sent via a chat session between two users. The fields are
+/// camelCase in JSON, e.g. `sourceUserId`.
+/// `id`: The message's unique ID, a freshly generated UUID when the client omits it.
 /// `source_user_id`: The sender's user ID
 /// `destination_user_id`: The recipient's user ID
-/// `timestamp`: The epoch millis that correspond with when the message was sent.
+/// `timestamp`: When the message was sent, which is epoch millis in JSON.
 /// `message`: The body of the message.
-#[derive(Serialize, Deserialize)]
+/// `deleted`: Whether the message has been deleted, in which case its body is empty.
+/// `read_at`: When the recipient read the message, or `None` if they haven't.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct Message<'a>
 {
-    #[serde(default)]
-    pub id: Option<&'a str>,
-    pub timestamp: u32,
-    pub message: &'a str,
-    pub sourceUserId: u32,
-    pub destinationUserId: u32,
+    #[serde(default = "generate_message_id")]
+    pub id: String,
+    pub timestamp: Timestamp,
+    // The text is only owned when it had to be unescaped, or the message outlives its source.
+    #[serde(borrow)]
+    pub message: Cow<'a, str>,
+    pub source_user_id: u32,
+    pub destination_user_id: u32,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub deleted: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub read_at: Option<Timestamp>,
+}
+
+impl<'a> Message<'a>
+{
+    /// Create a message sent now, with a freshly generated id. Messages created by the server
+    /// should use this rather than trusting a timestamp supplied by the client.
+    ///
+    /// # Parameters
+    ///
+    /// - `message`: the body of the message.
+    /// - `source_user_id`: the sender's user ID.
+    /// - `destination_user_id`: the recipient's user ID.
+    pub fn new<T: Into<Cow<'a, str>>>(message: T, source_user_id: u32, destination_user_id: u32) -> Message<'a>
+    {
+        Message
+        {
+            id: generate_message_id(),
+            timestamp: Timestamp::now(),
+            message: message.into(),
+            source_user_id,
+            destination_user_id,
+            deleted: false,
+            read_at: None,
+        }
+    }
+
+    /// Parse a message from JSON and validate it. See `parse_message()` and `validate()`.
+    ///
+    /// # Parameters
+    ///
+    /// - `json`: a reference to the `str` of the JSON to parse the message from.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The valid `Message`.
+    /// - `Err`: `ModelError::MissingField` or `ModelError::InvalidType` if a field is missing or
+    ///   of the wrong type, `ModelError::InvalidJson` if the JSON doesn't otherwise describe a
+    ///   message, or the `ModelError` of the validation that failed.
+    pub fn from_json(json: &'a str) -> Result<Message<'a>, ModelError>
+    {
+        let message = parse_message(json)?;
+        message.validate()?;
+
+        Ok(message)
+    }
+
+    /// Copy any text the message borrows so it no longer depends on the buffer it was parsed
+    /// from, e.g. to keep it in a store.
+    pub fn into_owned(self) -> Message<'static>
+    {
+        Message
+        {
+            id: self.id,
+            timestamp: self.timestamp,
+            message: Cow::Owned(self.message.into_owned()),
+            source_user_id: self.source_user_id,
+            destination_user_id: self.destination_user_id,
+            deleted: self.deleted,
+            read_at: self.read_at,
+        }
+    }
+
+    /// Validate that the message's text is no longer than `DEFAULT_MAX_MESSAGE_LENGTH`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The message is valid.
+    /// - `Err`: `ModelError::MessageTooLong` if the message's text is too long.
+    pub fn validate(&self) -> Result<(), ModelError>
+    {
+        self.validate_with_max_length(DEFAULT_MAX_MESSAGE_LENGTH)
+    }
+
+    /// Validate that the message's text is no longer than a maximum length. The length is
+    /// counted in Unicode scalar values (`char`s) rather than bytes, so a multi-byte character
+    /// counts once.
+    ///
+    /// # Parameters
+    ///
+    /// - `max_length`: the maximum number of characters the message's text may contain.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The message is valid.
+    /// - `Err`: `ModelError::MessageTooLong` if the message's text is too long.
+    pub fn validate_with_max_length(&self, max_length: usize) -> Result<(), ModelError>
+    {
+        let length = self.message.chars().count();
+        if length > max_length
+        {
+            return Err(ModelError::MessageTooLong { length, max_length });
+        }
+
+        Ok(())
+    }
+}
+
+/// # MessagePatch Struct
+///
+/// Struct that represents a partial update to a message, e.g. from a `PATCH /messages/:id`
+/// request. Only the fields that are present are changed. A message's id and users can't be
+/// changed, so a patch that includes them is rejected when it's parsed.
+/// `timestamp`: The new time the message was sent at, which is epoch millis in JSON.
+/// `message`: The new body of the message.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MessagePatch
+{
+    pub timestamp: Option<Timestamp>,
+    pub message: Option<String>,
+}
+
+impl MessagePatch
+{
+    /// Parse a patch from JSON.
+    ///
+    /// # Parameters
+    ///
+    /// - `json`: a reference to the `str` of the JSON to parse the patch from.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The `MessagePatch`.
+    /// - `Err`: `ModelError::InvalidJson` if the JSON is malformed or includes a field that
+    ///   can't be patched.
+    pub fn from_json(json: &str) -> Result<MessagePatch, ModelError>
+    {
+        Ok(serde_json::from_str(json)?)
+    }
+}
+
+/// Overwrite the fields of a message that are present in a patch, leaving the rest unchanged.
+///
+/// # Parameters
+///
+/// - `message`: the message to update.
+/// - `patch`: the fields to overwrite.
+pub fn apply_patch(message: &mut Message, patch: MessagePatch)
+{
+    if let Some(timestamp) = patch.timestamp
+    {
+        message.timestamp = timestamp;
+    }
+    if let Some(text) = patch.message
+    {
+        message.message = Cow::Owned(text);
+    }
+}
+
+/// Generate a random UUID v4 to identify a message.
+fn generate_message_id() -> String
+{
+    Uuid::new_v4().to_string()
 }
 
 /// Parses a Chat object from a request body.
@@ -47,10 +424,40 @@ pub struct Message<'a>
 ///
 /// - `Ok`: A `Chat` struct containing the chat object posted by the client.
 /// - `Err`: The error encountered when attempting to parse the request body.
-pub fn parse_chat(http_body: &str) -> Result<Chat>
+pub fn parse_chat(http_body: &str) -> serde_json::Result<Chat>
+{
+    serde_json::from_str(http_body)
+}
+
+/// Parses a Chat object from a request body, choosing whether unknown fields are an error.
+/// `parse_chat()` allows them.
+///
+/// # Parameters
+///
+/// - `http_body`: a reference to the `str` of the request body to parse a `Chat` object from.
+/// - `unknown_fields`: how fields that don't belong to a chat are treated.
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: A `Chat` struct containing the chat object posted by the client.
+/// - `Err`: The error encountered when attempting to parse the request body, including an
+///   unknown field when they're denied.
+pub fn parse_chat_with(http_body: &str, unknown_fields: UnknownFields) -> serde_json::Result<Chat>
 {
-    let chat= serde_json::from_str(http_body);
-    return chat;
+    let chat = parse_chat(http_body)?;
+    if unknown_fields == UnknownFields::Deny
+    {
+        // The body is known to be a valid chat object, so only its field names need checking.
+        let object: serde_json::Map<String, serde_json::Value> = serde_json::from_str(http_body)?;
+        if let Some(field) = object.keys().find(|field| !CHAT_FIELDS.contains(&field.as_str()))
+        {
+            return Err(serde_json::Error::unknown_field(field, CHAT_FIELDS));
+        }
+    }
+
+    Ok(chat)
 }
 
 /// Parses a Message object from a request body.
@@ -63,12 +470,85 @@ pub fn parse_chat(http_body: &str) -> Result<Chat>
 ///
 /// A `Result` which is:
 ///
-/// - `Ok`: A `Message` struct containing the message object posted by the client.
+/// - `Ok`: A `Message` struct containing the message object posted by the client. When the
+///   client doesn't supply an id, the message is given a freshly generated UUID.
 /// - `Err`: The error encountered when attempting to parse the request body.
-pub fn parse_message(http_body: &str) -> Result<Message>
+pub fn parse_message(http_body: &str) -> serde_json::Result<Message<'_>>
+{
+    serde_json::from_str(http_body)
+}
+
+/// Serializes a Chat object into a response body.
+///
+/// # Parameters
+///
+/// - `chat`: a reference to the `Chat` to serialize.
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: A `String` containing the JSON formatted chat object.
+/// - `Err`: The error encountered when attempting to serialize the chat.
+pub fn serialize_chat(chat: &Chat) -> serde_json::Result<String>
+{
+    serde_json::to_string(chat)
+}
+
+/// Serializes a Message object into a response body.
+///
+/// # Parameters
+///
+/// - `message`: a reference to the `Message` to serialize.
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: A `String` containing the JSON formatted message object.
+/// - `Err`: The error encountered when attempting to serialize the message.
+pub fn serialize_message(message: &Message) -> serde_json::Result<String>
+{
+    serde_json::to_string(message)
+}
+
+/// # JsonList Struct
+///
+/// Struct that wraps a list of models, e.g. the chats of a user, in the envelope list responses
+/// are sent in, which also gives the number of models in the list.
+/// `data`: The models in the list.
+/// `count`: The number of models in the list.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JsonList<'a, T>
 {
-    let message = serde_json::from_str(http_body);
-    return message;
+    data: &'a [T],
+    count: usize,
+}
+
+impl<'a, T: Serialize> JsonList<'a, T>
+{
+    /// Wrap a list of models.
+    pub fn new(data: &'a [T]) -> JsonList<'a, T>
+    {
+        JsonList
+        {
+            data,
+            count: data.len(),
+        }
+    }
+
+    /// Serializes the list into a response body, e.g. `{"data":[...],"count":2}`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: A `String` containing the JSON formatted list.
+    /// - `Err`: The error encountered when attempting to serialize one of the models.
+    pub fn to_json(&self) -> serde_json::Result<String>
+    {
+        serde_json::to_string(self)
+    }
 }
 
 #[cfg(test)]
@@ -90,13 +570,13 @@ mod test
         "#;
         let mut expected = Chat {
             id: Some(34),
-            participantIds: [3423, 9813],
+            participant_ids: [3423, 9813],
         };
-        let mut parsed_chat = parse_chat(&json_chat).unwrap();
+        let mut parsed_chat = parse_chat(json_chat).unwrap();
 
         assert_eq!(expected.id, parsed_chat.id);
-        assert_eq!(expected.participantIds[0], parsed_chat.participantIds[0]);
-        assert_eq!(expected.participantIds[1], parsed_chat.participantIds[1]);
+        assert_eq!(expected.participant_ids[0], parsed_chat.participant_ids[0]);
+        assert_eq!(expected.participant_ids[1], parsed_chat.participant_ids[1]);
 
         // Test the parsing of a JSON formatted chat object that does
         // not contain the id field.
@@ -107,13 +587,13 @@ mod test
         "#;
         expected = Chat {
             id: None,
-            participantIds: [3423, 9813],
+            participant_ids: [3423, 9813],
         };
-        parsed_chat = parse_chat(&json_chat).unwrap();
+        parsed_chat = parse_chat(json_chat).unwrap();
 
         assert_eq!(expected.id, parsed_chat.id);
-        assert_eq!(expected.participantIds[0], parsed_chat.participantIds[0]);
-        assert_eq!(expected.participantIds[1], parsed_chat.participantIds[1]);
+        assert_eq!(expected.participant_ids[0], parsed_chat.participant_ids[0]);
+        assert_eq!(expected.participant_ids[1], parsed_chat.participant_ids[1]);
     }
 
     /// Verify that the `parse_chat()` returns an error when parsing an incorrectly
@@ -127,7 +607,7 @@ mod test
                 "id": 34
             }
         "#;
-        let mut result = parse_chat(&json_chat).is_err();
+        let mut result = parse_chat(json_chat).is_err();
         assert!(result);
 
         // Test the parsing of a chat object that is not valid JSON
@@ -137,7 +617,7 @@ mod test
                 "participantIds": [3423, 9813]
             }
         "#;
-        result = parse_chat(&json_chat).is_err();
+        result = parse_chat(json_chat).is_err();
         assert!(result);
 
         json_chat = r#"
@@ -146,7 +626,7 @@ mod test
                 "participantIds": [3423, 9813],
             }
         "#;
-        result = parse_chat(&json_chat).is_err();
+        result = parse_chat(json_chat).is_err();
         assert!(result);
 
         json_chat = r#"
@@ -155,7 +635,7 @@ mod test
                 "participantIds": [3423, 9813]
             }
         "#;
-        result = parse_chat(&json_chat).is_err();
+        result = parse_chat(json_chat).is_err();
         assert!(result);
 
         json_chat = r#"
@@ -164,37 +644,475 @@ mod test
                 participantIds: [3423, 9813]
             }
         "#;
-        result = parse_chat(&json_chat).is_err();
+        result = parse_chat(json_chat).is_err();
         assert!(result);
     }
 
+    /// Verify that the `validate()` function accepts chats between two different users.
+    #[test]
+    fn test_chat_validate_valid()
+    {
+        let chat = parse_chat(r#"{"id": 34, "participantIds": [3423, 9813]}"#).unwrap();
+        assert_eq!(chat.validate(), Ok(()));
+
+        let chat = parse_chat(r#"{"participantIds": [1, 2]}"#).unwrap();
+        assert_eq!(chat.validate(), Ok(()));
+    }
+
+    /// Verify that the `validate()` function rejects chats whose participants are the same user
+    /// or include the reserved user id.
+    #[test]
+    fn test_chat_validate_invalid()
+    {
+        // Test that a chat of a user with themselves is rejected.
+        let mut chat = parse_chat(r#"{"id": 34, "participantIds": [3423, 3423]}"#).unwrap();
+        assert_eq!(chat.validate(), Err(ModelError::InvalidParticipants));
+
+        // Test that a chat with the reserved user id is rejected.
+        chat = parse_chat(r#"{"id": 34, "participantIds": [0, 9813]}"#).unwrap();
+        assert_eq!(chat.validate(), Err(ModelError::InvalidParticipants));
+
+        chat = parse_chat(r#"{"id": 34, "participantIds": [3423, 0]}"#).unwrap();
+        assert_eq!(chat.validate(), Err(ModelError::InvalidParticipants));
+
+        chat = parse_chat(r#"{"id": 34, "participantIds": [0, 0]}"#).unwrap();
+        assert_eq!(chat.validate(), Err(ModelError::InvalidParticipants));
+    }
+
     /// Verify that the `parse_message()` function correctly parses a `Message` struct from
     /// a JSON formatted HTTP body.
     #[test]
     fn test_parse_message_valid()
     {
-        let mut json_message = r#"
+        let json_message = r#"
             {
                 "id": "8911889c-8b93-4786-bbf3-50d56868b309",
-                "timestamp": 1572297339,
+                "timestamp": 1572297339000,
                 "message": "snake_case is more readable than CamelCase!",
                 "sourceUserId": 9837,
                 "destinationUserId": 1983
             }
         "#;
-        let mut expected = Message {
-            id: Some("8911889c-8b93-4786-bbf3-50d56868b309"),
-            timestamp: 1572297339,
-            message: "snake_case is more readable than CamelCase!",
-            sourceUserId: 9837,
-            destinationUserId: 1983,
+        let expected = Message {
+            id: String::from("8911889c-8b93-4786-bbf3-50d56868b309"),
+            timestamp: Timestamp::from_millis(1572297339000),
+            message: Cow::from("snake_case is more readable than CamelCase!"),
+            source_user_id: 9837,
+            destination_user_id: 1983,
+            deleted: false,
+            read_at: None,
         };
-        let mut parsed_message = parse_message(&json_message).unwrap();
+        let parsed_message = parse_message(json_message).unwrap();
 
         assert_eq!(expected.id, parsed_message.id);
         assert_eq!(expected.timestamp, parsed_message.timestamp);
         assert_eq!(expected.message, parsed_message.message);
-        assert_eq!(expected.sourceUserId, parsed_message.sourceUserId);
-        assert_eq!(expected.destinationUserId, parsed_message.destinationUserId);
+        assert_eq!(expected.source_user_id, parsed_message.source_user_id);
+        assert_eq!(expected.destination_user_id, parsed_message.destination_user_id);
+    }
+
+    /// Verify that the `parse_message()` function generates a UUID for messages that don't
+    /// supply an id, and keeps the id of messages that do.
+    #[test]
+    fn test_parse_message_id()
+    {
+        // Test that an omitted id is replaced with a valid UUID.
+        let mut json_message = r#"
+            {
+                "timestamp": 1572297339000,
+                "message": "snake_case is more readable than CamelCase!",
+                "sourceUserId": 9837,
+                "destinationUserId": 1983
+            }
+        "#;
+        let first = parse_message(json_message).unwrap();
+        let second = parse_message(json_message).unwrap();
+
+        let uuid = Uuid::parse_str(&first.id).unwrap();
+        assert_eq!(uuid.get_version_num(), 4);
+        assert_ne!(first.id, second.id);
+
+        // Test that a supplied id is preserved verbatim, even if it isn't a UUID.
+        json_message = r#"
+            {
+                "id": "not-a-uuid",
+                "timestamp": 1572297339000,
+                "message": "snake_case is more readable than CamelCase!",
+                "sourceUserId": 9837,
+                "destinationUserId": 1983
+            }
+        "#;
+        let parsed_message = parse_message(json_message).unwrap();
+        assert_eq!(parsed_message.id, "not-a-uuid");
+    }
+
+    /// Verify that the `parse_message()` function doesn't truncate timestamps that don't fit in
+    /// 32 bits, such as the current time in epoch millis.
+    #[test]
+    fn test_parse_message_large_timestamp()
+    {
+        let json_message = r#"
+            {
+                "timestamp": 1572297339000,
+                "message": "snake_case is more readable than CamelCase!",
+                "sourceUserId": 9837,
+                "destinationUserId": 1983
+            }
+        "#;
+        let parsed_message = parse_message(json_message).unwrap();
+
+        assert!(parsed_message.timestamp.as_millis() > u64::from(u32::MAX));
+        assert_eq!(parsed_message.timestamp, Timestamp::from_millis(1572297339000));
+    }
+
+    /// Verify that the `parse_message()` function unescapes message text, and that
+    /// `into_owned()` keeps the text of a message that borrows it.
+    #[test]
+    fn test_parse_message_escaped_text()
+    {
+        let json_message = String::from(r#"
+            {
+                "timestamp": 1572297339000,
+                "message": "She said \"Hi\"",
+                "sourceUserId": 9837,
+                "destinationUserId": 1983
+            }
+        "#);
+        let owned_message = parse_message(&json_message).unwrap().into_owned();
+        drop(json_message);
+        assert_eq!(owned_message.message, "She said \"Hi\"");
+    }
+
+    /// Verify that the `serialize_chat()` function produces JSON that parses back into the same
+    /// chat object.
+    #[test]
+    fn test_serialize_chat()
+    {
+        let json_chat = r#"
+            {
+                "id": 34,
+                "participantIds": [3423, 9813]
+            }
+        "#;
+        let parsed_chat = parse_chat(json_chat).unwrap();
+        let serialized = serialize_chat(&parsed_chat).unwrap();
+
+        assert_eq!(serialized, r#"{"id":34,"participantIds":[3423,9813]}"#);
+        assert_eq!(parse_chat(&serialized).unwrap(), parsed_chat);
+
+        // Test that the JSON is semantically equal to the original.
+        let original: serde_json::Value = serde_json::from_str(json_chat).unwrap();
+        let round_tripped: serde_json::Value = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(original, round_tripped);
+    }
+
+    /// Verify that the `serialize_message()` function produces JSON that parses back into the
+    /// same message object.
+    #[test]
+    fn test_serialize_message()
+    {
+        let json_message = r#"
+            {
+                "id": "8911889c-8b93-4786-bbf3-50d56868b309",
+                "timestamp": 1572297339000,
+                "message": "snake_case is more readable than CamelCase!",
+                "sourceUserId": 9837,
+                "destinationUserId": 1983
+            }
+        "#;
+        let parsed_message = parse_message(json_message).unwrap();
+        let serialized = serialize_message(&parsed_message).unwrap();
+
+        assert_eq!(parse_message(&serialized).unwrap(), parsed_message);
+
+        // Test that the JSON is semantically equal to the original.
+        let original: serde_json::Value = serde_json::from_str(json_message).unwrap();
+        let round_tripped: serde_json::Value = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(original, round_tripped);
+    }
+
+    /// Build a message with the given text for validation tests.
+    fn message_with_text(text: &str) -> Message<'_>
+    {
+        Message {
+            id: String::from("8911889c-8b93-4786-bbf3-50d56868b309"),
+            timestamp: Timestamp::from_millis(1572297339000),
+            message: Cow::from(text),
+            source_user_id: 9837,
+            destination_user_id: 1983,
+            deleted: false,
+            read_at: None,
+        }
+    }
+
+    /// Verify that the `validate()` function accepts messages up to the maximum length and
+    /// rejects longer ones.
+    #[test]
+    fn test_message_validate_length()
+    {
+        // Test that a message exactly at the limit is accepted.
+        let mut text = "a".repeat(DEFAULT_MAX_MESSAGE_LENGTH);
+        assert_eq!(message_with_text(&text).validate(), Ok(()));
+
+        // Test that a message one character over the limit is rejected.
+        text.push('a');
+        assert_eq!(
+            message_with_text(&text).validate(),
+            Err(ModelError::MessageTooLong { length: DEFAULT_MAX_MESSAGE_LENGTH + 1, max_length: DEFAULT_MAX_MESSAGE_LENGTH })
+        );
+
+        // Test that multi-byte characters are counted once each.
+        text = "\u{e9}".repeat(DEFAULT_MAX_MESSAGE_LENGTH);
+        assert_eq!(message_with_text(&text).validate(), Ok(()));
+    }
+
+    /// Verify that the `validate_with_max_length()` function enforces a custom maximum length.
+    #[test]
+    fn test_message_validate_custom_length()
+    {
+        let message = message_with_text("Hello");
+        assert_eq!(message.validate_with_max_length(5), Ok(()));
+        assert_eq!(message.validate_with_max_length(4), Err(ModelError::MessageTooLong { length: 5, max_length: 4 }));
+        assert_eq!(message_with_text("").validate_with_max_length(0), Ok(()));
+    }
+
+    /// Verify that the `Chat::from_json()` function parses chats like `parse_chat()` does, and
+    /// rejects invalid participants.
+    #[test]
+    fn test_chat_from_json()
+    {
+        let mut json_chat = r#"{"id": 34, "participantIds": [3423, 9813]}"#;
+        assert_eq!(Chat::from_json(json_chat), Ok(parse_chat(json_chat).unwrap()));
+
+        json_chat = r#"{"participantIds": [3423, 9813]}"#;
+        assert_eq!(Chat::from_json(json_chat), Ok(parse_chat(json_chat).unwrap()));
+
+        // Test that malformed JSON is rejected by both.
+        json_chat = r#"{"id": 34"#;
+        let error = parse_chat(json_chat).unwrap_err();
+        assert_eq!(Chat::from_json(json_chat), Err(ModelError::InvalidJson(error.to_string())));
+
+        // Test that a missing field is named.
+        json_chat = r#"{"id": 34}"#;
+        assert!(parse_chat(json_chat).is_err());
+        assert_eq!(Chat::from_json(json_chat), Err(ModelError::MissingField(String::from("participantIds"))));
+
+        // Test that a field of the wrong type is told apart from malformed JSON.
+        json_chat = r#"{"id": "34", "participantIds": [3423, 9813]}"#;
+        let error = parse_chat(json_chat).unwrap_err();
+        assert_eq!(Chat::from_json(json_chat), Err(ModelError::InvalidType(error.to_string())));
+        assert_eq!(Chat::from_json(json_chat).unwrap_err().code(), "invalid_type");
+
+        // Test that invalid participants are rejected.
+        json_chat = r#"{"id": 34, "participantIds": [3423, 3423]}"#;
+        assert_eq!(Chat::from_json(json_chat), Err(ModelError::InvalidParticipants));
+
+        json_chat = r#"{"id": 34, "participantIds": [0, 9813]}"#;
+        assert_eq!(Chat::from_json(json_chat), Err(ModelError::InvalidParticipants));
+    }
+
+    /// Verify that the `Message::from_json()` function parses messages like `parse_message()`
+    /// does, and rejects messages that are too long.
+    #[test]
+    fn test_message_from_json()
+    {
+        let json_message = r#"
+            {
+                "id": "8911889c-8b93-4786-bbf3-50d56868b309",
+                "timestamp": 1572297339000,
+                "message": "snake_case is more readable than CamelCase!",
+                "sourceUserId": 9837,
+                "destinationUserId": 1983
+            }
+        "#;
+        assert_eq!(Message::from_json(json_message), Ok(parse_message(json_message).unwrap()));
+
+        // Test that malformed JSON is rejected.
+        let mut result = Message::from_json(r#"{"timestamp": 1572297339000"#);
+        assert!(matches!(result, Err(ModelError::InvalidJson(_))));
+
+        result = Message::from_json(r#"{"timestamp": 1572297339000}"#);
+        assert_eq!(result, Err(ModelError::MissingField(String::from("message"))));
+
+        result = Message::from_json(r#"{"timestamp": "yesterday", "message": "Hi"}"#);
+        assert!(matches!(result, Err(ModelError::InvalidType(_))));
+
+        // Test that a message that's too long is rejected.
+        let json_message = format!(
+            r#"{{"timestamp": 1572297339000, "message": "{}", "sourceUserId": 9837, "destinationUserId": 1983}}"#,
+            "a".repeat(DEFAULT_MAX_MESSAGE_LENGTH + 1)
+        );
+        result = Message::from_json(&json_message);
+        assert_eq!(
+            result,
+            Err(ModelError::MessageTooLong { length: DEFAULT_MAX_MESSAGE_LENGTH + 1, max_length: DEFAULT_MAX_MESSAGE_LENGTH })
+        );
+    }
+
+    /// Verify that the `apply_patch()` function only overwrites the fields present in a patch.
+    #[test]
+    fn test_apply_patch()
+    {
+        // Test that a text-only patch leaves the timestamp unchanged.
+        let mut message = message_with_text("Hello");
+        apply_patch(&mut message, MessagePatch::from_json(r#"{"message": "Hello, world!"}"#).unwrap());
+        assert_eq!(message.message, "Hello, world!");
+        assert_eq!(message.timestamp, Timestamp::from_millis(1572297339000));
+
+        // Test that a timestamp-only patch leaves the text unchanged.
+        apply_patch(&mut message, MessagePatch { timestamp: Some(Timestamp::from_millis(1572297400000)), message: None });
+        assert_eq!(message.message, "Hello, world!");
+        assert_eq!(message.timestamp, Timestamp::from_millis(1572297400000));
+
+        // Test that an empty patch changes nothing.
+        let expected = message.clone();
+        apply_patch(&mut message, MessagePatch::from_json("{}").unwrap());
+        assert_eq!(message, expected);
+    }
+
+    /// Verify that the `MessagePatch::from_json()` function rejects patches that try to change
+    /// a message's id or users.
+    #[test]
+    fn test_message_patch_immutable_fields()
+    {
+        let patches = [
+            r#"{"id": "8911889c-8b93-4786-bbf3-50d56868b309"}"#,
+            r#"{"message": "Hello", "sourceUserId": 1}"#,
+            r#"{"destinationUserId": 1}"#,
+        ];
+        for patch in patches.iter()
+        {
+            assert!(matches!(MessagePatch::from_json(patch), Err(ModelError::InvalidJson(_))), "{}", patch);
+        }
+    }
+
+    /// Verify that a message's `deleted` flag defaults to false and is only serialized when set.
+    #[test]
+    fn test_message_deleted()
+    {
+        let mut message = parse_message(
+            r#"{"id": "1", "timestamp": 1572297339000, "message": "Hello", "sourceUserId": 9837, "destinationUserId": 1983}"#
+        ).unwrap();
+        assert!(!message.deleted);
+        assert!(!serialize_message(&message).unwrap().contains("deleted"));
+
+        message.deleted = true;
+        let serialized = serialize_message(&message).unwrap();
+        assert!(serialized.contains(r#""deleted":true"#));
+        assert!(parse_message(&serialized).unwrap().deleted);
+    }
+
+    /// Verify that the `parse_chat_with()` function only rejects unknown fields when they're
+    /// denied.
+    #[test]
+    fn test_parse_chat_unknown_fields()
+    {
+        let json_chat = r#"{"id": 34, "participantIds": [3423, 9813], "participentIds": [1, 2]}"#;
+
+        // Test that a misspelled field is ignored when unknown fields are allowed.
+        let chat = parse_chat_with(json_chat, UnknownFields::Allow).unwrap();
+        assert_eq!(chat, Chat { id: Some(34), participant_ids: [3423, 9813] });
+        assert_eq!(parse_chat(json_chat).unwrap(), chat);
+
+        // Test that a misspelled field is an error when unknown fields are denied.
+        let error = parse_chat_with(json_chat, UnknownFields::Deny).unwrap_err();
+        assert!(error.to_string().contains("unknown field `participentIds`"), "{}", error);
+        assert!(matches!(Chat::from_json(json_chat), Err(ModelError::InvalidJson(_))));
+
+        // Test that a misspelled field replacing a required one is an error either way.
+        let json_chat = r#"{"id": 34, "participentIds": [3423, 9813]}"#;
+        assert!(parse_chat_with(json_chat, UnknownFields::Allow).is_err());
+        assert!(parse_chat_with(json_chat, UnknownFields::Deny).is_err());
+
+        // Test that a chat with only known fields is accepted either way.
+        let json_chat = r#"{"participantIds": [3423, 9813]}"#;
+        assert_eq!(parse_chat_with(json_chat, UnknownFields::Deny).unwrap(), parse_chat(json_chat).unwrap());
+    }
+
+    /// Verify that the snake_case fields of `Chat` and `Message` are camelCase in JSON.
+    #[test]
+    fn test_camel_case_wire_format()
+    {
+        let chat = Chat { id: Some(34), participant_ids: [3423, 9813] };
+        assert_eq!(serialize_chat(&chat).unwrap(), r#"{"id":34,"participantIds":[3423,9813]}"#);
+
+        let message = message_with_text("Hello");
+        let serialized: serde_json::Value = serde_json::from_str(&serialize_message(&message).unwrap()).unwrap();
+        let expected = serde_json::json!({
+            "id": "8911889c-8b93-4786-bbf3-50d56868b309",
+            "timestamp": 1572297339000_u64,
+            "message": "Hello",
+            "sourceUserId": 9837,
+            "destinationUserId": 1983,
+        });
+        assert_eq!(serialized, expected);
+
+        // Test that snake_case field names aren't accepted in place of the camelCase ones.
+        assert!(parse_chat(r#"{"id": 34, "participant_ids": [3423, 9813]}"#).is_err());
+    }
+
+    /// Verify that the `Timestamp` constructors convert between seconds and milliseconds, and
+    /// that timestamps are written as epoch millis in JSON.
+    #[test]
+    fn test_timestamp()
+    {
+        let timestamp = Timestamp::from_secs(1572297339);
+        assert_eq!(timestamp, Timestamp::from_millis(1572297339000));
+        assert_eq!(timestamp.as_millis(), 1572297339000);
+        assert_eq!(timestamp.as_secs(), 1572297339);
+        assert_eq!(Timestamp::from_millis(1572297339999).as_secs(), 1572297339);
+        assert_eq!(Timestamp::from_secs(u64::MAX).as_millis(), u64::MAX);
+
+        assert_eq!(serde_json::to_string(&timestamp).unwrap(), "1572297339000");
+        assert_eq!(serde_json::from_str::<Timestamp>("1572297339000").unwrap(), timestamp);
+
+        // Test that a message's timestamps survive a round trip through JSON.
+        let mut message = message_with_text("Hello");
+        message.read_at = Some(Timestamp::from_millis(1572297400000));
+        let json = serialize_message(&message).unwrap();
+        assert!(json.contains(r#""timestamp":1572297339000"#));
+        assert!(json.contains(r#""readAt":1572297400000"#));
+        assert_eq!(parse_message(&json).unwrap(), message);
+    }
+
+    /// Verify that the `Message::new()` function stamps messages with the current time and a
+    /// unique id.
+    #[test]
+    fn test_message_new()
+    {
+        let before = Timestamp::now();
+        let first = Message::new("Hello", 9837, 1983);
+        let second = Message::new(String::from("Hi!"), 1983, 9837);
+
+        assert!(first.timestamp >= before);
+        assert!(second.timestamp >= first.timestamp);
+        assert_ne!(first.id, second.id);
+        assert_eq!(Uuid::parse_str(&first.id).unwrap().get_version_num(), 4);
+
+        assert_eq!(first.message, "Hello");
+        assert_eq!((first.source_user_id, first.destination_user_id), (9837, 1983));
+        assert!(!first.deleted);
+
+        // Test that the timestamp is in epoch millis rather than seconds.
+        assert!(first.timestamp.as_millis() > 1_000_000_000_000);
+    }
+
+    /// Verify that the `to_json()` function wraps a list of models in an envelope with its
+    /// count.
+    #[test]
+    fn test_json_list_to_json()
+    {
+        // Test that an empty list has a count of 0.
+        let mut chats = vec![];
+        let mut json = JsonList::<Chat>::new(&chats).to_json().unwrap();
+        assert_eq!(json, r#"{"data":[],"count":0}"#);
+
+        chats = vec![
+            Chat { id: Some(34), participant_ids: [3423, 9813] },
+            Chat { id: None, participant_ids: [3423, 1983] },
+        ];
+        json = JsonList::new(&chats).to_json().unwrap();
+        let expected = r#"{"data":[{"id":34,"participantIds":[3423,9813]},{"id":null,"participantIds":[3423,1983]}],"count":2}"#;
+        assert_eq!(json, expected);
     }
 }
\ No newline at end of file
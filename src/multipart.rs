@@ -0,0 +1,292 @@
+use crate::http;
+use crate::models::ModelError;
+
+/// A part of a `multipart/form-data` body, i.e. a field of a form or a file uploaded with it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultipartField<'a>
+{
+    name: &'a str,
+    filename: Option<&'a str>,
+    headers: Vec<(&'a str, &'a str)>,
+    content: &'a [u8],
+}
+
+impl<'a> MultipartField<'a>
+{
+    /// Returns the name of the field, from the `name` parameter of its `Content-Disposition`.
+    pub fn name(&self) -> &'a str
+    {
+        self.name
+    }
+
+    /// Returns the name of the uploaded file, from the `filename` parameter of the field's
+    /// `Content-Disposition`, if the field is a file.
+    pub fn filename(&self) -> Option<&'a str>
+    {
+        self.filename
+    }
+
+    /// Returns every header of the part as `(name, value)` pairs in the order they were received.
+    pub fn headers(&self) -> &[(&'a str, &'a str)]
+    {
+        &self.headers
+    }
+
+    /// Look up the value of a header of the part by name, ignoring the case of the name.
+    pub fn header(&self, name: &str) -> Option<&'a str>
+    {
+        http::find_header(&self.headers, name)
+    }
+
+    /// Returns the value of the part's `Content-Type` header. Fields without one are plain text.
+    pub fn content_type(&self) -> Option<&'a str>
+    {
+        self.header("Content-Type")
+    }
+
+    /// Returns the raw content of the part, i.e. the value of the field or the content of the
+    /// file.
+    pub fn content(&self) -> &'a [u8]
+    {
+        self.content
+    }
+}
+
+/// Parse the fields of a `multipart/form-data` body.
+///
+/// A multipart body looks like: Preamble *( "--" Boundary CRLF Part CRLF ) "--" Boundary "--" Epilogue
+/// where each part looks like: *( Header CRLF ) CRLF Content
+/// The preamble and epilogue are ignored. The headers of each part must be text, but its content
+/// can be any bytes, e.g. an uploaded image.
+///
+/// # Parameters
+///
+/// - `content_type`: the `Content-Type` of the body, whose `boundary` parameter separates the
+///   parts, e.g. `multipart/form-data; boundary=XyZ`.
+/// - `body`: a reference to the bytes of the body.
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: A `Vec` of the fields in the order they appear in the body.
+/// - `Err`: `ModelError::InvalidForm` if the content type doesn't have a boundary, the body
+///   isn't terminated by the closing boundary or a part is malformed.
+pub fn parse_multipart<'a>(content_type: &str, body: &'a [u8]) -> Result<Vec<MultipartField<'a>>, ModelError>
+{
+    let boundary = boundary(content_type).ok_or(ModelError::InvalidForm)?;
+    let delimiter = format!("\r\n--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+
+    // Only the delimiters following a part are preceded by a CRLF, the first one can start the
+    // body.
+    let mut rest = match body.strip_prefix(&delimiter[2 ..])
+    {
+        Some(rest) => rest,
+        None => {
+            let start = find(body, delimiter).ok_or(ModelError::InvalidForm)?;
+            &body[start + delimiter.len() ..]
+        },
+    };
+
+    let mut fields = Vec::new();
+    // Every delimiter is either the closing one, or is followed by a line break and a part.
+    while !rest.starts_with(b"--")
+    {
+        let padding = rest.iter().take_while(|byte| **byte == b' ' || **byte == b'\t').count();
+        let part = rest[padding ..].strip_prefix(b"\r\n").ok_or(ModelError::InvalidForm)?;
+        let end = find(part, delimiter).ok_or(ModelError::InvalidForm)?;
+
+        fields.push(parse_part(&part[.. end])?);
+        rest = &part[end + delimiter.len() ..];
+    }
+
+    Ok(fields)
+}
+
+/// Find the position of the first occurrence of `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize>
+{
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Find the boundary of a multipart body in the `boundary` parameter of its content type.
+fn boundary(content_type: &str) -> Option<&str>
+{
+    content_type.split(';')
+        .skip(1)
+        .filter_map(|parameter| parameter.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value.trim().trim_matches('"'))
+        .filter(|boundary| !boundary.is_empty())
+}
+
+/// Parse a part of a multipart body, without the delimiters surrounding it.
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `Ok`: The field of the part.
+/// - `Err`: `ModelError::InvalidForm` if a header is malformed or isn't valid UTF-8, or the part
+///   doesn't have a `Content-Disposition` of `form-data` with a name.
+fn parse_part(part: &[u8]) -> Result<MultipartField<'_>, ModelError>
+{
+    // A part without headers starts with the empty line separating them from the content.
+    let (head, content) = match part.strip_prefix(b"\r\n")
+    {
+        Some(content) => (&part[.. 0], content),
+        None => {
+            let end = find(part, b"\r\n\r\n").ok_or(ModelError::InvalidForm)?;
+            (&part[.. end], &part[end + 4 ..])
+        },
+    };
+    let head = std::str::from_utf8(head).map_err(|_| ModelError::InvalidForm)?;
+    let headers = head.split("\r\n")
+        .filter(|line| !line.is_empty())
+        .map(|line| http::split_header_line(line).map_err(|_| ModelError::InvalidForm))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // A disposition looks like: "form-data" *( ";" Parameter "=" ( Token / Quoted-String ) )
+    let disposition = http::find_header(&headers, "Content-Disposition").ok_or(ModelError::InvalidForm)?;
+    let mut parameters = disposition.split(';');
+    if !parameters.next().unwrap_or("").trim().eq_ignore_ascii_case("form-data")
+    {
+        return Err(ModelError::InvalidForm);
+    }
+
+    let mut name = None;
+    let mut filename = None;
+    for (parameter, value) in parameters.filter_map(|parameter| parameter.split_once('='))
+    {
+        let value = value.trim().trim_matches('"');
+        match parameter.trim()
+        {
+            "name" => name = Some(value),
+            "filename" => filename = Some(value),
+            _ => {},
+        }
+    }
+
+    Ok(
+        MultipartField
+        {
+            name: name.ok_or(ModelError::InvalidForm)?,
+            filename,
+            headers,
+            content,
+        }
+    )
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const CONTENT_TYPE: &str = "multipart/form-data; boundary=XyZ";
+
+    /// Verify that the `parse_multipart()` function parses a body with a single text field.
+    #[test]
+    fn test_parse_multipart_text()
+    {
+        let body = "--XyZ\r\nContent-Disposition: form-data; name=\"message\"\r\n\r\nHello there!\r\n--XyZ--\r\n";
+        let fields = parse_multipart(CONTENT_TYPE, body.as_bytes()).unwrap();
+
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].name(), "message");
+        assert_eq!(fields[0].filename(), None);
+        assert_eq!(fields[0].content_type(), None);
+        assert_eq!(fields[0].content(), b"Hello there!");
+        assert_eq!(fields[0].headers(), &[("Content-Disposition", "form-data; name=\"message\"")]);
+    }
+
+    /// Verify that the `parse_multipart()` function parses a body with a text field and a file,
+    /// ignoring the preamble and epilogue.
+    #[test]
+    fn test_parse_multipart_file()
+    {
+        let body = "This is the preamble.\r\n\
+                    --XyZ\r\n\
+                    Content-Disposition: form-data; name=\"sourceUserId\"\r\n\
+                    \r\n\
+                    9837\r\n\
+                    --XyZ\r\n\
+                    Content-Disposition: form-data; name=\"attachment\"; filename=\"image.bin\"\r\n\
+                    Content-Type: application/octet-stream\r\n\
+                    \r\n\
+                    \x00\x01\x02\r\n--X\x7f\r\n\
+                    --XyZ--\r\n\
+                    This is the epilogue.";
+        let fields = parse_multipart("multipart/form-data; charset=utf-8; boundary=\"XyZ\"", body.as_bytes()).unwrap();
+
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields[0].name(), "sourceUserId");
+        assert_eq!(fields[0].content(), b"9837");
+
+        // Test that content resembling the delimiter is kept.
+        assert_eq!(fields[1].name(), "attachment");
+        assert_eq!(fields[1].filename(), Some("image.bin"));
+        assert_eq!(fields[1].content_type(), Some("application/octet-stream"));
+        assert_eq!(fields[1].content(), b"\x00\x01\x02\r\n--X\x7f");
+
+        // Test that a body without any fields is empty.
+        assert_eq!(parse_multipart(CONTENT_TYPE, b"--XyZ--"), Ok(Vec::new()));
+    }
+
+    /// Verify that the `parse_multipart()` function keeps the content of a file that isn't
+    /// valid UTF-8 as it is.
+    #[test]
+    fn test_parse_multipart_binary()
+    {
+        let mut body = b"--XyZ\r\n\
+                         Content-Disposition: form-data; name=\"attachment\"; filename=\"image.png\"\r\n\
+                         Content-Type: image/png\r\n\
+                         \r\n\
+                         \x89PNG\r\n\x1a\n\xff\xfe\x80\r\n\
+                         --XyZ--\r\n".to_vec();
+        let fields = parse_multipart(CONTENT_TYPE, &body).unwrap();
+
+        assert_eq!(fields.len(), 1);
+        assert_eq!(fields[0].filename(), Some("image.png"));
+        assert_eq!(fields[0].content_type(), Some("image/png"));
+        assert_eq!(fields[0].content(), b"\x89PNG\r\n\x1a\n\xff\xfe\x80");
+
+        // Test that the headers of a part must still be text.
+        body = b"--XyZ\r\nContent-Disposition: form-data; name=\"\xff\"\r\n\r\nHello\r\n--XyZ--".to_vec();
+        assert_eq!(parse_multipart(CONTENT_TYPE, &body), Err(ModelError::InvalidForm));
+    }
+
+    /// Verify that the `parse_multipart()` function rejects malformed bodies.
+    #[test]
+    fn test_parse_multipart_invalid()
+    {
+        let field = "Content-Disposition: form-data; name=\"message\"\r\n\r\nHello";
+
+        // Test that the boundary is required.
+        let mut body = format!("--XyZ\r\n{}\r\n--XyZ--", field);
+        assert_eq!(parse_multipart("multipart/form-data", body.as_bytes()), Err(ModelError::InvalidForm));
+        assert_eq!(parse_multipart("multipart/form-data; boundary=", body.as_bytes()), Err(ModelError::InvalidForm));
+        assert_eq!(parse_multipart("multipart/form-data; boundary=Other", body.as_bytes()), Err(ModelError::InvalidForm));
+
+        // Test that the closing delimiter is required.
+        body = format!("--XyZ\r\n{}\r\n--XyZ", field);
+        assert_eq!(parse_multipart(CONTENT_TYPE, body.as_bytes()), Err(ModelError::InvalidForm));
+        body = format!("--XyZ\r\n{}", field);
+        assert_eq!(parse_multipart(CONTENT_TYPE, body.as_bytes()), Err(ModelError::InvalidForm));
+
+        // Test that parts must be named form-data.
+        let parts = [
+            "\r\nHello",
+            "Content-Type: text/plain\r\n\r\nHello",
+            "Content-Disposition: form-data\r\n\r\nHello",
+            "Content-Disposition: attachment; name=\"message\"\r\n\r\nHello",
+            "Content-Disposition form-data; name=\"message\"\r\n\r\nHello",
+        ];
+        for part in parts.iter()
+        {
+            body = format!("--XyZ\r\n{}\r\n--XyZ--", part);
+            assert_eq!(parse_multipart(CONTENT_TYPE, body.as_bytes()), Err(ModelError::InvalidForm), "{:?}", part);
+        }
+    }
+}
\ No newline at end of file
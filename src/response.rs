@@ -0,0 +1,871 @@
+use std::io::Write;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::http::{ByteRange, HttpRequest, ParseError};
+use crate::models::ModelError;
+
+/// The offset basis of the 64-bit FNV-1a hash `etag()` computes.
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// The prime of the 64-bit FNV-1a hash `etag()` computes.
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// The status codes a response can be sent with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StatusCode
+{
+    Continue = 100,
+    SwitchingProtocols = 101,
+    Ok = 200,
+    Created = 201,
+    Accepted = 202,
+    NoContent = 204,
+    PartialContent = 206,
+    MovedPermanently = 301,
+    Found = 302,
+    NotModified = 304,
+    BadRequest = 400,
+    Unauthorized = 401,
+    Forbidden = 403,
+    NotFound = 404,
+    MethodNotAllowed = 405,
+    NotAcceptable = 406,
+    RequestTimeout = 408,
+    Conflict = 409,
+    LengthRequired = 411,
+    PayloadTooLarge = 413,
+    UnsupportedMediaType = 415,
+    RangeNotSatisfiable = 416,
+    UpgradeRequired = 426,
+    TooManyRequests = 429,
+    InternalServerError = 500,
+    NotImplemented = 501,
+    ServiceUnavailable = 503,
+}
+
+impl StatusCode
+{
+    /// Returns the numeric status code, e.g. `404`.
+    pub fn code(&self) -> u16
+    {
+        *self as u16
+    }
+
+    /// Returns the canonical text describing the status code, e.g. `"Not Found"`.
+    pub fn reason_phrase(&self) -> &'static str
+    {
+        match self
+        {
+            StatusCode::Continue => "Continue",
+            StatusCode::SwitchingProtocols => "Switching Protocols",
+            StatusCode::Ok => "OK",
+            StatusCode::Created => "Created",
+            StatusCode::Accepted => "Accepted",
+            StatusCode::NoContent => "No Content",
+            StatusCode::PartialContent => "Partial Content",
+            StatusCode::MovedPermanently => "Moved Permanently",
+            StatusCode::Found => "Found",
+            StatusCode::NotModified => "Not Modified",
+            StatusCode::BadRequest => "Bad Request",
+            StatusCode::Unauthorized => "Unauthorized",
+            StatusCode::Forbidden => "Forbidden",
+            StatusCode::NotFound => "Not Found",
+            StatusCode::MethodNotAllowed => "Method Not Allowed",
+            StatusCode::NotAcceptable => "Not Acceptable",
+            StatusCode::RequestTimeout => "Request Timeout",
+            StatusCode::Conflict => "Conflict",
+            StatusCode::LengthRequired => "Length Required",
+            StatusCode::PayloadTooLarge => "Payload Too Large",
+            StatusCode::UnsupportedMediaType => "Unsupported Media Type",
+            StatusCode::RangeNotSatisfiable => "Range Not Satisfiable",
+            StatusCode::UpgradeRequired => "Upgrade Required",
+            StatusCode::TooManyRequests => "Too Many Requests",
+            StatusCode::InternalServerError => "Internal Server Error",
+            StatusCode::NotImplemented => "Not Implemented",
+            StatusCode::ServiceUnavailable => "Service Unavailable",
+        }
+    }
+
+    /// Returns whether a response with the status code can have a body. Informational
+    /// responses, `204 No Content` and `304 Not Modified` can't.
+    pub fn allows_body(&self) -> bool
+    {
+        self.code() >= 200 && *self != StatusCode::NoContent && *self != StatusCode::NotModified
+    }
+
+    /// Look up the `StatusCode` for a numeric status code.
+    ///
+    /// # Parameters
+    ///
+    /// - `code`: the numeric status code, e.g. `404`.
+    ///
+    /// # Returns
+    ///
+    /// An `Option` which is:
+    ///
+    /// - `Some`: The `StatusCode` matching the numeric code.
+    /// - `None`: The numeric code isn't one of the supported status codes.
+    pub fn from_u16(code: u16) -> Option<StatusCode>
+    {
+        match code
+        {
+            100 => Some(StatusCode::Continue),
+            101 => Some(StatusCode::SwitchingProtocols),
+            200 => Some(StatusCode::Ok),
+            201 => Some(StatusCode::Created),
+            202 => Some(StatusCode::Accepted),
+            204 => Some(StatusCode::NoContent),
+            206 => Some(StatusCode::PartialContent),
+            301 => Some(StatusCode::MovedPermanently),
+            302 => Some(StatusCode::Found),
+            304 => Some(StatusCode::NotModified),
+            400 => Some(StatusCode::BadRequest),
+            401 => Some(StatusCode::Unauthorized),
+            403 => Some(StatusCode::Forbidden),
+            404 => Some(StatusCode::NotFound),
+            405 => Some(StatusCode::MethodNotAllowed),
+            406 => Some(StatusCode::NotAcceptable),
+            408 => Some(StatusCode::RequestTimeout),
+            409 => Some(StatusCode::Conflict),
+            411 => Some(StatusCode::LengthRequired),
+            413 => Some(StatusCode::PayloadTooLarge),
+            415 => Some(StatusCode::UnsupportedMediaType),
+            416 => Some(StatusCode::RangeNotSatisfiable),
+            426 => Some(StatusCode::UpgradeRequired),
+            429 => Some(StatusCode::TooManyRequests),
+            500 => Some(StatusCode::InternalServerError),
+            501 => Some(StatusCode::NotImplemented),
+            503 => Some(StatusCode::ServiceUnavailable),
+            _ => None,
+        }
+    }
+}
+
+/// Represents an outgoing HTTP response
+#[derive(Debug, Clone, PartialEq)]
+pub struct HttpResponse
+{
+    status: StatusCode,
+    headers: Vec<(String, String)>,
+    body: Option<Vec<u8>>,
+}
+
+impl HttpResponse
+{
+    /// Create a response without any headers or a body.
+    ///
+    /// # Parameters
+    ///
+    /// - `status`: the status code of the response, e.g. `StatusCode::Ok`.
+    pub fn new(status: StatusCode) -> HttpResponse
+    {
+        HttpResponse
+        {
+            status,
+            headers: Vec::new(),
+            body: None,
+        }
+    }
+
+    /// Start building a response, which is a `200 OK` without any headers or a body until the
+    /// builder is told otherwise.
+    pub fn builder() -> HttpResponseBuilder
+    {
+        HttpResponseBuilder::new()
+    }
+
+    /// Create a `200 OK` response with the given body.
+    pub fn ok<B: Into<Vec<u8>>>(body: B) -> HttpResponse
+    {
+        let mut response = HttpResponse::new(StatusCode::Ok);
+        response.set_body(body);
+        response
+    }
+
+    /// Create a `201 Created` response for a resource created by the request, e.g. a chat.
+    ///
+    /// # Parameters
+    ///
+    /// - `location`: the path of the new resource, e.g. `"/chats/34"`, for the `Location` header.
+    /// - `body`: the body of the response, usually the new resource.
+    pub fn created<B: Into<Vec<u8>>>(location: &str, body: B) -> HttpResponse
+    {
+        let mut response = HttpResponse::new(StatusCode::Created);
+        response.add_header("Location", location);
+        response.set_body(body);
+        response
+    }
+
+    /// Create the response to a request for a range of the given body, which is a
+    /// `206 Partial Content` with the bytes of the range and a `Content-Range` header describing
+    /// them, e.g. `bytes 100-199/1000`. A range that starts past the end of the body, or ends
+    /// before it starts, gets a `416 Range Not Satisfiable` without a body, whose `Content-Range` is e.g. `bytes */1000`.
+    ///
+    /// # Parameters
+    ///
+    /// - `body`: the whole body the range is taken from.
+    /// - `range`: the range the request asks for. See `HttpRequest::range()`.
+    pub fn partial_content<B: Into<Vec<u8>>>(body: B, range: ByteRange) -> HttpResponse
+    {
+        let body = body.into();
+        let length = body.len() as u64;
+
+        match range.bounds(length)
+        {
+            Some((start, end)) => {
+                let mut response = HttpResponse::new(StatusCode::PartialContent);
+                response.add_header("Content-Range", &format!("bytes {}-{}/{}", start, end, length));
+                response.set_body(&body[start as usize ..= end as usize]);
+                response
+            },
+            None => {
+                let mut response = HttpResponse::new(StatusCode::RangeNotSatisfiable);
+                response.add_header("Content-Range", &format!("bytes */{}", length));
+                response
+            },
+        }
+    }
+
+    /// Create a `204 No Content` response, e.g. to a successful `DELETE` request. It's sent
+    /// without a body or a `Content-Length` header.
+    pub fn no_content() -> HttpResponse
+    {
+        HttpResponse::new(StatusCode::NoContent)
+    }
+
+    /// Create a `404 Not Found` response without a body.
+    pub fn not_found() -> HttpResponse
+    {
+        HttpResponse::new(StatusCode::NotFound)
+    }
+
+    /// Returns the status code of the response.
+    pub fn status(&self) -> StatusCode
+    {
+        self.status
+    }
+
+    /// Returns every header of the response as `(name, value)` pairs in the order they were added.
+    pub fn headers(&self) -> &[(String, String)]
+    {
+        &self.headers
+    }
+
+    /// Look up the value of a header by name, ignoring the case of the name.
+    pub fn header(&self, name: &str) -> Option<&str>
+    {
+        self.headers.iter()
+            .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Add a header to the response. Headers that are added more than once are sent once for
+    /// every value.
+    pub fn add_header(&mut self, name: &str, value: &str)
+    {
+        self.headers.push((String::from(name), String::from(value)));
+    }
+
+    /// Returns the body of the response, if the response has one.
+    pub fn body(&self) -> Option<&[u8]>
+    {
+        self.body.as_deref()
+    }
+
+    /// Replace the body of the response.
+    pub fn set_body<B: Into<Vec<u8>>>(&mut self, body: B)
+    {
+        self.body = Some(body.into());
+    }
+
+    /// Remove the body of the response to a `HEAD` request. The response keeps the
+    /// `Content-Length` header it would have had with its body, so a `HEAD` request is told the
+    /// length of the body a `GET` request would get.
+    pub fn strip_body(&mut self)
+    {
+        if let Some(body) = self.body.take()
+        {
+            if self.header("Content-Length").is_none() && self.status.allows_body()
+            {
+                self.add_header("Content-Length", &body.len().to_string());
+            }
+        }
+    }
+
+    /// Gzip-compress the body of the response if the client that sent the request accepts gzip,
+    /// setting the `Content-Encoding` header to `gzip`. A `Content-Length` header the response
+    /// already has is removed, so the length of the compressed body is sent instead. A response
+    /// without a body, whose body is already encoded or that's a range of a body with a
+    /// `Content-Range` header, is left as it is. Otherwise, the response varies on the request's
+    /// `Accept-Encoding` header, so a `Vary` header is added whether or not it's compressed.
+    ///
+    /// # Parameters
+    ///
+    /// - `request`: a reference to the request the response is being sent to.
+    pub fn compress_for(&mut self, request: &HttpRequest)
+    {
+        // Compressing a range would leave its Content-Range describing the uncompressed bytes.
+        if self.body.is_none() || self.header("Content-Encoding").is_some() || self.header("Content-Range").is_some()
+        {
+            return;
+        }
+        self.add_header("Vary", "Accept-Encoding");
+
+        if let Some(body) = self.body.as_deref().filter(|_| request.accepts_encoding("gzip"))
+        {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            // Writing to a `Vec` can't fail.
+            encoder.write_all(body).expect("Failed to compress the body!");
+            self.body = Some(encoder.finish().expect("Failed to compress the body!"));
+            self.headers.retain(|(name, _)| !name.eq_ignore_ascii_case("Content-Length"));
+            self.add_header("Content-Encoding", "gzip");
+        }
+    }
+
+    /// Serialize the response into the bytes sent to the client.
+    ///
+    /// A response looks like: HTTP-Version SP Status-Code SP Reason-Phrase CRLF *( Header CRLF ) CRLF Body
+    /// Unless the response already has one, the `Content-Length` header is computed from the
+    /// body and appended to the headers. Responses whose status code doesn't allow a body, e.g.
+    /// `101 Switching Protocols` or `204 No Content`, are sent without a body or a computed
+    /// `Content-Length`. See `StatusCode::allows_body()`.
+    ///
+    /// # Returns
+    ///
+    /// A `Vec` of the bytes of the serialized HTTP/1.1 response.
+    pub fn to_bytes(&self) -> Vec<u8>
+    {
+        let body = self.body.as_deref().filter(|_| self.status.allows_body()).unwrap_or(&[]);
+        let mut bytes = format!("HTTP/1.1 {} {}\r\n", self.status.code(), self.status.reason_phrase()).into_bytes();
+
+        for (name, value) in &self.headers
+        {
+            bytes.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        if self.header("Content-Length").is_none() && self.status.allows_body()
+        {
+            bytes.extend_from_slice(format!("Content-Length: {}\r\n", body.len()).as_bytes());
+        }
+        bytes.extend_from_slice(b"\r\n");
+        bytes.extend_from_slice(body);
+
+        bytes
+    }
+}
+
+impl From<ParseError> for HttpResponse
+{
+    /// Create the response to a request that couldn't be parsed, which is a `400 Bad Request`,
+    /// or a `413 Payload Too Large` for a request that's too large. The body is JSON describing
+    /// the error, e.g. `{"error":"unsupported_method","detail":"Unsupported method!"}`, where
+    /// `error` is the stable `ParseError::code()`.
+    fn from(error: ParseError) -> HttpResponse
+    {
+        let status = match error
+        {
+            ParseError::RequestTooLarge { .. } | ParseError::BodyTooLarge { .. } => StatusCode::PayloadTooLarge,
+            _ => StatusCode::BadRequest,
+        };
+        let body = serde_json::json!({ "error": error.code(), "detail": error.to_string() });
+
+        HttpResponse::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+            .build()
+    }
+}
+
+impl From<ModelError> for HttpResponse
+{
+    /// Create the response to a request whose body isn't a valid model, which is a
+    /// `400 Bad Request`, or a `415 Unsupported Media Type` for a body of the wrong media type.
+    /// The body is JSON describing the error like for a `ParseError`, e.g.
+    /// `{"error":"message_too_long","detail":"..."}`, where `error` is the stable
+    /// `ModelError::code()`. A missing field is named in a `field` member.
+    fn from(error: ModelError) -> HttpResponse
+    {
+        let status = match error
+        {
+            ModelError::UnsupportedMediaType => StatusCode::UnsupportedMediaType,
+            _ => StatusCode::BadRequest,
+        };
+        let mut body = serde_json::json!({ "error": error.code(), "detail": error.to_string() });
+        if let ModelError::MissingField(field) = &error
+        {
+            body["field"] = serde_json::Value::from(field.as_str());
+        }
+
+        HttpResponse::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+            .build()
+    }
+}
+
+/// Builds a `HttpResponse` from chained calls, e.g.
+/// `HttpResponse::builder().status(StatusCode::Created).header("Location", "/chats/34").build()`.
+#[derive(Debug, Clone)]
+pub struct HttpResponseBuilder
+{
+    response: HttpResponse,
+}
+
+impl Default for HttpResponseBuilder
+{
+    fn default() -> HttpResponseBuilder
+    {
+        HttpResponseBuilder { response: HttpResponse::new(StatusCode::Ok) }
+    }
+}
+
+impl HttpResponseBuilder
+{
+    /// Start building a `200 OK` response without any headers or a body.
+    pub fn new() -> HttpResponseBuilder
+    {
+        HttpResponseBuilder::default()
+    }
+
+    /// Set the status code of the response.
+    pub fn status(mut self, status: StatusCode) -> HttpResponseBuilder
+    {
+        self.response.status = status;
+        self
+    }
+
+    /// Add a header to the response. A `Content-Length` header overrides the length that's
+    /// otherwise computed from the body.
+    pub fn header(mut self, name: &str, value: &str) -> HttpResponseBuilder
+    {
+        self.response.add_header(name, value);
+        self
+    }
+
+    /// Set the body of the response.
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> HttpResponseBuilder
+    {
+        self.response.set_body(body);
+        self
+    }
+
+    /// Finish building the response.
+    pub fn build(self) -> HttpResponse
+    {
+        self.response
+    }
+}
+
+/// Compute a strong entity tag for a response body, e.g. `"af63dc4c8601ec8c"`, for its `ETag`
+/// header. The tag is the 64-bit FNV-1a hash of the body, so identical bodies have the same tag,
+/// even across restarts of the server.
+pub fn etag(body: &[u8]) -> String
+{
+    let hash = body.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME));
+    format!("\"{:016x}\"", hash)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+    use crate::http::parse_request;
+    use crate::models::{Chat, Message, DEFAULT_MAX_MESSAGE_LENGTH};
+
+    /// Verify that the `to_bytes()` function serializes a `200 OK` response with a body.
+    #[test]
+    fn test_to_bytes_ok()
+    {
+        let mut response = HttpResponse::ok("{\"id\":34,\"participantIds\":[3423,9813]}");
+        response.add_header("Content-Type", "application/json");
+
+        let expected = b"HTTP/1.1 200 OK\r\n\
+            Content-Type: application/json\r\n\
+            Content-Length: 38\r\n\
+            \r\n\
+            {\"id\":34,\"participantIds\":[3423,9813]}";
+        assert_eq!(response.to_bytes(), expected.to_vec());
+    }
+
+    /// Verify that the `created()` function builds a `201 Created` response with a `Location`.
+    #[test]
+    fn test_created()
+    {
+        let response = HttpResponse::created("/chats/34", "{\"id\":34,\"participantIds\":[3423,9813]}");
+
+        let expected = b"HTTP/1.1 201 Created\r\n\
+            Location: /chats/34\r\n\
+            Content-Length: 38\r\n\
+            \r\n\
+            {\"id\":34,\"participantIds\":[3423,9813]}";
+        assert_eq!(response.status(), StatusCode::Created);
+        assert_eq!(response.header("Location"), Some("/chats/34"));
+        assert_eq!(response.to_bytes(), expected.to_vec());
+    }
+
+    /// Verify that the `to_bytes()` function serializes responses whose status doesn't allow a
+    /// body without one or a `Content-Length` header.
+    #[test]
+    fn test_to_bytes_no_content()
+    {
+        let mut response = HttpResponse::no_content();
+        assert_eq!(response.status(), StatusCode::NoContent);
+        assert_eq!(response.to_bytes(), b"HTTP/1.1 204 No Content\r\n\r\n".to_vec());
+
+        // Test that a body is never sent with a 204 or 304.
+        response.set_body("Hello");
+        assert_eq!(response.to_bytes(), b"HTTP/1.1 204 No Content\r\n\r\n".to_vec());
+        response = HttpResponse::builder().status(StatusCode::NotModified).header("ETag", "\"v1\"").body("Hello").build();
+        assert_eq!(response.to_bytes(), b"HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\n\r\n".to_vec());
+
+        // Test that other responses still have a length, even without a body.
+        assert_eq!(HttpResponse::ok("Hello").to_bytes(), b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nHello".to_vec());
+        assert_eq!(HttpResponse::new(StatusCode::Ok).to_bytes(), b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n".to_vec());
+    }
+
+    /// Verify that the `to_bytes()` function serializes a `404 Not Found` response without a body.
+    #[test]
+    fn test_to_bytes_not_found()
+    {
+        let response = HttpResponse::not_found();
+
+        assert_eq!(response.status(), StatusCode::NotFound);
+        assert_eq!(response.body(), None);
+        assert_eq!(response.to_bytes(), b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_vec());
+    }
+
+    /// Verify that headers added to a response are serialized in the order they were added.
+    #[test]
+    fn test_to_bytes_headers()
+    {
+        let mut response = HttpResponse::new(StatusCode::Ok);
+        response.add_header("Set-Cookie", "a=1");
+        response.add_header("Set-Cookie", "b=2");
+        response.add_header("Cache-Control", "no-cache");
+
+        assert_eq!(response.header("set-cookie"), Some("a=1"));
+        assert_eq!(response.header("Content-Type"), None);
+        assert_eq!(
+            response.to_bytes(),
+            b"HTTP/1.1 200 OK\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\nCache-Control: no-cache\r\nContent-Length: 0\r\n\r\n".to_vec()
+        );
+    }
+
+    /// Verify that the `StatusCode` enum maps to and from numeric codes and reason phrases.
+    #[test]
+    fn test_status_code()
+    {
+        assert_eq!(StatusCode::Ok.code(), 200);
+        assert_eq!(StatusCode::Ok.reason_phrase(), "OK");
+        assert_eq!(StatusCode::MethodNotAllowed.code(), 405);
+        assert_eq!(StatusCode::MethodNotAllowed.reason_phrase(), "Method Not Allowed");
+        assert_eq!(StatusCode::InternalServerError.reason_phrase(), "Internal Server Error");
+
+        assert_eq!(StatusCode::from_u16(201), Some(StatusCode::Created));
+        assert_eq!(StatusCode::from_u16(404), Some(StatusCode::NotFound));
+        assert_eq!(StatusCode::from_u16(418), None);
+        assert_eq!(StatusCode::from_u16(0), None);
+    }
+
+    /// Verify that the status line of a serialized response uses the status code's reason phrase.
+    #[test]
+    fn test_to_bytes_status_line()
+    {
+        let statuses = [
+            (StatusCode::NotFound, "HTTP/1.1 404 Not Found\r\n"),
+            (StatusCode::BadRequest, "HTTP/1.1 400 Bad Request\r\n"),
+            (StatusCode::Created, "HTTP/1.1 201 Created\r\n"),
+            (StatusCode::ServiceUnavailable, "HTTP/1.1 503 Service Unavailable\r\n"),
+            (StatusCode::UpgradeRequired, "HTTP/1.1 426 Upgrade Required\r\n"),
+        ];
+        for (status, status_line) in statuses.iter()
+        {
+            let bytes = HttpResponse::new(*status).to_bytes();
+            assert!(bytes.starts_with(status_line.as_bytes()));
+        }
+    }
+
+    /// Verify that the `HttpResponseBuilder` builds a response with several headers and a body.
+    #[test]
+    fn test_builder()
+    {
+        let response = HttpResponse::builder()
+            .status(StatusCode::Created)
+            .header("Content-Type", "application/json")
+            .header("Location", "/chats/34")
+            .body("{\"id\":34}")
+            .build();
+
+        assert_eq!(response.status(), StatusCode::Created);
+        assert_eq!(response.header("location"), Some("/chats/34"));
+        assert_eq!(response.body(), Some(&b"{\"id\":34}"[..]));
+        assert_eq!(
+            response.to_bytes(),
+            b"HTTP/1.1 201 Created\r\nContent-Type: application/json\r\nLocation: /chats/34\r\nContent-Length: 9\r\n\r\n{\"id\":34}".to_vec()
+        );
+    }
+
+    /// Verify that the `HttpResponseBuilder` defaults to a `200 OK` without a body.
+    #[test]
+    fn test_builder_defaults()
+    {
+        let response = HttpResponseBuilder::new().build();
+
+        assert_eq!(response, HttpResponse::new(StatusCode::Ok));
+        assert_eq!(response.to_bytes(), b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n".to_vec());
+    }
+
+    /// Verify that a `Content-Length` header set on a response overrides the computed length.
+    #[test]
+    fn test_builder_content_length_override()
+    {
+        let response = HttpResponse::builder()
+            .header("Content-Length", "38")
+            .build();
+
+        assert_eq!(response.to_bytes(), b"HTTP/1.1 200 OK\r\nContent-Length: 38\r\n\r\n".to_vec());
+    }
+
+    /// Verify that a `ParseError` converts into a response whose JSON body describes the error.
+    #[test]
+    fn test_from_parse_error()
+    {
+        let response = HttpResponse::from(ParseError::InvalidHeader(String::from("X Name: chatty")));
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+
+        let body: serde_json::Value = serde_json::from_slice(response.body().unwrap()).unwrap();
+        assert_eq!(body["error"], "invalid_header");
+        assert_eq!(body["detail"], "Invalid header: \"X Name: chatty\"");
+
+        // Test that a body that's too large gets its own status code.
+        let response = HttpResponse::from(ParseError::BodyTooLarge { max_body_bytes: 5 });
+        assert_eq!(response.status(), StatusCode::PayloadTooLarge);
+    }
+
+    /// Verify that each kind of `ParseError` has a distinct error code in its response.
+    #[test]
+    fn test_from_parse_error_codes()
+    {
+        let errors = [
+            (ParseError::BadRequest, "bad_request"),
+            (ParseError::MissingMethod, "missing_method"),
+            (ParseError::UnsupportedMethod, "unsupported_method"),
+            (ParseError::MissingUri, "missing_uri"),
+            (ParseError::InvalidPercentEncoding, "invalid_percent_encoding"),
+            (ParseError::UnsafePath, "unsafe_path"),
+            (ParseError::InvalidTarget, "invalid_target"),
+            (ParseError::InvalidId { index: 1 }, "invalid_id"),
+            (ParseError::BareLineFeed, "bare_line_feed"),
+            (ParseError::MissingVersion, "missing_version"),
+            (ParseError::UnsupportedVersion, "unsupported_version"),
+            (ParseError::MalformedHeader, "malformed_header"),
+            (ParseError::InvalidHost, "invalid_host"),
+            (ParseError::InvalidAuthorization, "invalid_authorization"),
+            (ParseError::InvalidContentLength, "invalid_content_length"),
+            (ParseError::RequestTooLarge { max_request_bytes: 5 }, "request_too_large"),
+            (ParseError::ContentLengthMismatch { expected: 5, actual: 4 }, "content_length_mismatch"),
+            (ParseError::ConflictingFraming, "conflicting_framing"),
+            (ParseError::UnsupportedTransferEncoding, "unsupported_transfer_encoding"),
+            (ParseError::InvalidChunkSize, "invalid_chunk_size"),
+            (ParseError::MalformedChunk, "malformed_chunk"),
+            (ParseError::InvalidUtf8 { offset: 7 }, "invalid_utf8"),
+        ];
+        let mut codes = Vec::new();
+        for (error, code) in errors.iter()
+        {
+            let response = HttpResponse::from(error.clone());
+            let body: serde_json::Value = serde_json::from_slice(response.body().unwrap()).unwrap();
+            assert_eq!(body["error"], *code);
+            assert_eq!(body["detail"], error.to_string());
+            codes.push(*code);
+        }
+
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+    }
+
+    /// Verify that the `compress_for()` method gzips the body when the client accepts gzip.
+    #[test]
+    fn test_compress_for_gzip()
+    {
+        let body = "{\"id\":34,\"participantIds\":[3423,9813]}".repeat(100);
+        let request = parse_request("GET /chats HTTP/1.1\r\nAccept-Encoding: gzip, deflate\r\n\r\n").unwrap();
+        let mut response = HttpResponse::ok(body.as_str());
+        response.compress_for(&request);
+
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.header("Vary"), Some("Accept-Encoding"));
+        let compressed = response.body().unwrap().to_vec();
+        assert!(compressed.len() < body.len());
+
+        let mut decompressed = String::new();
+        GzDecoder::new(&compressed[..]).read_to_string(&mut decompressed).unwrap();
+        assert_eq!(decompressed, body);
+
+        // Test that the `Content-Length` is the length of the compressed body.
+        let bytes = String::from_utf8_lossy(&response.to_bytes()).into_owned();
+        assert!(bytes.contains(&format!("Content-Length: {}\r\n", compressed.len())));
+
+        // Test that an already encoded body isn't compressed twice.
+        let before = response.clone();
+        response.compress_for(&request);
+        assert_eq!(response, before);
+
+        // Test that a `Content-Length` of the uncompressed body is replaced.
+        response = HttpResponse::ok(body.as_str());
+        response.add_header("content-length", &body.len().to_string());
+        response.compress_for(&request);
+        assert_eq!(response.header("Content-Length"), None);
+        let bytes = String::from_utf8_lossy(&response.to_bytes()).into_owned();
+        assert!(bytes.contains(&format!("Content-Length: {}\r\n", response.body().unwrap().len())));
+    }
+
+    /// Verify that the `compress_for()` method leaves the body uncompressed when the client
+    /// doesn't accept gzip, or the response isn't one to compress.
+    #[test]
+    fn test_compress_for_identity()
+    {
+        let requests = [
+            "GET /chats HTTP/1.1\r\n",
+            "GET /chats HTTP/1.1\r\nAccept-Encoding: br\r\n\r\n",
+            "GET /chats HTTP/1.1\r\nAccept-Encoding: gzip;q=0\r\n\r\n",
+        ];
+        for raw_request in requests.iter()
+        {
+            let request = parse_request(raw_request).unwrap();
+            let mut response = HttpResponse::ok("{\"id\":34}");
+            response.compress_for(&request);
+
+            assert_eq!(response.header("Content-Encoding"), None);
+            assert_eq!(response.body(), Some(&b"{\"id\":34}"[..]));
+            assert!(response.to_bytes().ends_with(b"Content-Length: 9\r\n\r\n{\"id\":34}"));
+        }
+
+        // Test that a response without a body is left as it is.
+        let request = parse_request("GET /chats HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n").unwrap();
+        let mut response = HttpResponse::not_found();
+        response.compress_for(&request);
+        assert_eq!(response, HttpResponse::not_found());
+
+        // Test that a range of a body is left as it is, so its Content-Range stays accurate.
+        let body = "{\"id\":34,\"participantIds\":[3423,9813]}".repeat(100);
+        let range = HttpResponse::partial_content(body.as_str(), ByteRange { start: 0, end: Some(999) });
+        response = range.clone();
+        response.compress_for(&request);
+        assert_eq!(response, range);
+        assert_eq!(response.header("Content-Range"), Some(format!("bytes 0-999/{}", body.len()).as_str()));
+        assert_eq!(response.body().map(<[u8]>::len), Some(1000));
+    }
+
+    /// Verify that a `ModelError` converts into a `400 Bad Request` whose JSON body describes
+    /// the error.
+    #[test]
+    fn test_from_model_error()
+    {
+        let json_message = format!(
+            r#"{{"timestamp": 1572297339, "message": "{}", "sourceUserId": 9837, "destinationUserId": 1983}}"#,
+            "a".repeat(DEFAULT_MAX_MESSAGE_LENGTH + 1)
+        );
+        let mut response = HttpResponse::from(Message::from_json(&json_message).unwrap_err());
+        assert_eq!(response.status(), StatusCode::BadRequest);
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+        let mut body: serde_json::Value = serde_json::from_slice(response.body().unwrap()).unwrap();
+        assert_eq!(body["error"], "message_too_long");
+        assert_eq!(body["detail"], "A message can't be longer than 4096 characters, got 4097!");
+
+        // Test that a chat with the same user twice has its own code.
+        let json_chat = r#"{"id": 34, "participantIds": [3423, 3423]}"#;
+        response = HttpResponse::from(Chat::from_json(json_chat).unwrap_err());
+        body = serde_json::from_slice(response.body().unwrap()).unwrap();
+        assert_eq!(body["error"], "invalid_participants");
+
+        // Test that a missing field is named.
+        response = HttpResponse::from(ModelError::MissingField(String::from("participantIds")));
+        body = serde_json::from_slice(response.body().unwrap()).unwrap();
+        assert_eq!(body["error"], "missing_field");
+        assert_eq!(body["field"], "participantIds");
+
+        // Test that a body that isn't JSON gets its own status code.
+        response = HttpResponse::from(ModelError::UnsupportedMediaType);
+        assert_eq!(response.status(), StatusCode::UnsupportedMediaType);
+
+        response = HttpResponse::from(ModelError::InvalidJson(String::from("EOF while parsing")));
+        body = serde_json::from_slice(response.body().unwrap()).unwrap();
+        assert_eq!(body["error"], "invalid_json");
+        assert_eq!(body.get("field"), None);
+    }
+
+    /// Verify that the `strip_body()` function removes the body but keeps its length.
+    #[test]
+    fn test_strip_body()
+    {
+        let mut response = HttpResponse::ok("Hello");
+        let expected = response.to_bytes();
+        response.strip_body();
+        assert_eq!(response.body(), None);
+        assert_eq!(response.header("Content-Length"), Some("5"));
+        assert_eq!(response.to_bytes(), expected[.. expected.len() - 5].to_vec());
+
+        // Test that an existing Content-Length isn't duplicated, and stripping again does nothing.
+        response.strip_body();
+        assert_eq!(response.headers().len(), 1);
+
+        response = HttpResponse::new(StatusCode::NoContent);
+        response.strip_body();
+        assert_eq!(response.to_bytes(), HttpResponse::new(StatusCode::NoContent).to_bytes());
+    }
+
+    /// Verify that the `partial_content()` function sends the requested range of the body.
+    #[test]
+    fn test_partial_content()
+    {
+        let mut response = HttpResponse::partial_content("Hello, world!", ByteRange { start: 7, end: Some(11) });
+        assert_eq!(
+            response.to_bytes(),
+            b"HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 7-11/13\r\nContent-Length: 5\r\n\r\nworld".to_vec()
+        );
+
+        // Test that an open-ended range, or one that ends past the body, runs to the last byte.
+        response = HttpResponse::partial_content("Hello, world!", ByteRange { start: 7, end: None });
+        assert_eq!(response.header("Content-Range"), Some("bytes 7-12/13"));
+        assert_eq!(response.body(), Some(&b"world!"[..]));
+
+        response = HttpResponse::partial_content("Hello, world!", ByteRange { start: 0, end: Some(99) });
+        assert_eq!(response.header("Content-Range"), Some("bytes 0-12/13"));
+        assert_eq!(response.body(), Some(&b"Hello, world!"[..]));
+
+        // Test that a range starting past the end of the body can't be satisfied.
+        response = HttpResponse::partial_content("Hello, world!", ByteRange { start: 13, end: Some(20) });
+        assert_eq!(
+            response.to_bytes(),
+            b"HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */13\r\nContent-Length: 0\r\n\r\n".to_vec()
+        );
+
+        // Test that a range that ends before it starts can't be satisfied either.
+        response = HttpResponse::partial_content("Hello, world!", ByteRange { start: 7, end: Some(3) });
+        assert_eq!(response.status(), StatusCode::RangeNotSatisfiable);
+        assert_eq!(response.header("Content-Range"), Some("bytes */13"));
+        assert_eq!(response.body(), None);
+    }
+
+    /// Verify that the `etag()` function computes the same tag for identical bodies only.
+    #[test]
+    fn test_etag()
+    {
+        assert_eq!(etag(b""), "\"cbf29ce484222325\"");
+        assert_eq!(etag(b"a"), "\"af63dc4c8601ec8c\"");
+
+        let body = br#"[{"id":34,"participantIds":[3423,9813]}]"#;
+        assert_eq!(etag(body), etag(br#"[{"id":34,"participantIds":[3423,9813]}]"#));
+        assert_ne!(etag(body), etag(br#"[{"id":35,"participantIds":[3423,9813]}]"#));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,767 @@
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::http::{HttpRequest, Method, ParseError, RequestTarget};
+use crate::models::JsonList;
+use crate::response::{self, HttpResponse, StatusCode};
+use crate::store::ChatStore;
+
+/// The signature of the closures that handle a routed request.
+pub type Handler = Box<dyn Fn(&HttpRequest, &Params) -> HttpResponse + Send + Sync>;
+
+/// The values captured from the `:param` segments of a route's path pattern.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Params
+{
+    values: Vec<(String, String)>,
+}
+
+impl Params
+{
+    /// Look up the value captured for a parameter by name, e.g. `"id"` for `/chats/:id`.
+    pub fn get(&self, name: &str) -> Option<&str>
+    {
+        self.values.iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A segment of a route's path pattern.
+enum Segment
+{
+    /// A segment that must match the request path exactly.
+    Literal(String),
+    /// A `:name` segment that matches any single segment of the request path.
+    Param(String),
+}
+
+/// A handler registered for a method and path pattern.
+struct Route
+{
+    method: Method,
+    segments: Vec<Segment>,
+    handler: Handler,
+}
+
+impl Route
+{
+    /// Match the route's path pattern against the segments of a request path.
+    ///
+    /// # Parameters
+    ///
+    /// - `path`: the segments of the request path.
+    /// - `normalize_trailing_slash`: whether to ignore a trailing `/` of the pattern, in which
+    ///   case the path mustn't have one either.
+    ///
+    /// # Returns
+    ///
+    /// An `Option` which is:
+    ///
+    /// - `Some`: The parameters captured from the path if it matches the pattern.
+    /// - `None`: The path doesn't match the pattern.
+    fn matches(&self, path: &[&str], normalize_trailing_slash: bool) -> Option<Params>
+    {
+        let mut segments = &self.segments[..];
+        if normalize_trailing_slash
+        {
+            if let [rest @ .., Segment::Literal(last)] = segments
+            {
+                // The root path is a single empty segment, which is kept.
+                if last.is_empty() && !rest.is_empty()
+                {
+                    segments = rest;
+                }
+            }
+        }
+        if path.len() != segments.len()
+        {
+            return None;
+        }
+
+        let mut params = Params::default();
+        for (segment, value) in segments.iter().zip(path)
+        {
+            match segment
+            {
+                Segment::Literal(literal) if literal == value => (),
+                Segment::Literal(_) => return None,
+                Segment::Param(_) if value.is_empty() => return None,
+                Segment::Param(name) => params.values.push((name.clone(), String::from(*value))),
+            }
+        }
+
+        Some(params)
+    }
+}
+
+/// Dispatches parsed requests to the handler registered for their method and path.
+#[derive(Default)]
+pub struct Router
+{
+    routes: Vec<Route>,
+    normalize_trailing_slash: bool,
+}
+
+impl Router
+{
+    /// Create a router without any routes.
+    pub fn new() -> Router
+    {
+        Router::default()
+    }
+
+    /// Set whether paths with and without a trailing `/`, e.g. `/messages/` and `/messages`,
+    /// are the same path, for both the routes that are registered and the requests that are
+    /// routed. By default they're different paths.
+    pub fn normalize_trailing_slash(mut self, normalize: bool) -> Router
+    {
+        self.normalize_trailing_slash = normalize;
+        self
+    }
+
+    /// Register a handler for a method and path pattern.
+    ///
+    /// # Parameters
+    ///
+    /// - `method`: the method of the requests to handle.
+    /// - `pattern`: the path of the requests to handle. A segment starting with `:`, e.g. the
+    ///   `:id` in `/chats/:id`, matches any value and captures it in the handler's `Params`.
+    /// - `handler`: the closure that produces the response for a matching request.
+    pub fn add<F>(&mut self, method: Method, pattern: &str, handler: F)
+    where
+        F: Fn(&HttpRequest, &Params) -> HttpResponse + Send + Sync + 'static,
+    {
+        let segments = split_path(pattern).into_iter()
+            .map(|segment| match segment.strip_prefix(':')
+            {
+                Some(name) => Segment::Param(String::from(name)),
+                None => Segment::Literal(String::from(segment)),
+            })
+            .collect();
+
+        self.routes.push(Route { method, segments, handler: Box::new(handler) });
+    }
+
+    /// Register the health check routes operators probe a deployment with. `GET /healthz` is the
+    /// liveness check, which always responds with `200 OK` while the server is handling requests.
+    /// `GET /readyz` is the readiness check, which responds with `200 OK` when the server is
+    /// ready to handle traffic and `503 Service Unavailable` otherwise.
+    ///
+    /// # Parameters
+    ///
+    /// - `ready`: the closure that reports whether the server is ready, e.g. whether the store
+    ///   has been initialized. It's called on every readiness check.
+    pub fn add_health_checks<F>(&mut self, ready: F)
+    where
+        F: Fn() -> bool + Send + Sync + 'static,
+    {
+        self.add(Method::Get, "/healthz", |_, _| HttpResponse::ok("ok"));
+        self.add(Method::Get, "/readyz", move |_, _| {
+            if ready()
+            {
+                HttpResponse::ok("ready")
+            }
+            else
+            {
+                HttpResponse::builder()
+                    .status(StatusCode::ServiceUnavailable)
+                    .body("not ready")
+                    .build()
+            }
+        });
+    }
+
+    /// Register `GET /users/:id/chats`, which lists the chats a user participates in as JSON,
+    /// e.g. `{"data":[{"id":34,"participantIds":[3423,9813]}],"count":1}`. A user who isn't in
+    /// any chats, including a user the store has never seen, gets an empty list. An id that isn't
+    /// a number is answered with a `400 Bad Request`.
+    ///
+    /// # Parameters
+    ///
+    /// - `store`: the store the chats are looked up in.
+    pub fn add_user_chats(&mut self, store: Arc<ChatStore>)
+    {
+        self.add(Method::Get, "/users/:id/chats", move |_, params| {
+            let user_id = match params.get("id")
+                .filter(|id| id.bytes().all(|b| b.is_ascii_digit()))
+                .and_then(|id| id.parse().ok())
+            {
+                Some(user_id) => user_id,
+                // Reject the id like `HttpRequest::path_id()` would, as the path's second segment.
+                None => return HttpResponse::from(ParseError::InvalidId { index: 1 }),
+            };
+
+            let chats = store.list_chats_for_user(user_id);
+            match JsonList::new(&chats).to_json()
+            {
+                Ok(json) => HttpResponse::builder().header("Content-Type", "application/json").body(json).build(),
+                Err(_) => HttpResponse::new(StatusCode::InternalServerError),
+            }
+        });
+    }
+
+    /// Dispatch a request to the handler registered for its method and path.
+    ///
+    /// # Parameters
+    ///
+    /// - `request`: a reference to the parsed request to dispatch.
+    ///
+    /// # Returns
+    ///
+    /// The `HttpResponse` produced by the matching handler, a `405 Method Not Allowed` with an
+    /// `Allow` header listing the registered methods if handlers are only registered for the
+    /// path with other methods, or a `404 Not Found` if no handler is registered for the path at
+    /// all. A `HEAD` request for a path without a HEAD route is handled by its GET route, and
+    /// answered with the headers of that response but without its body. An `OPTIONS *` request
+    /// is answered with a `200 OK` whose `Allow` header lists every method the server handles.
+    pub fn route(&self, request: &HttpRequest) -> HttpResponse
+    {
+        if let RequestTarget::Asterisk = request.target()
+        {
+            let mut allowed_methods = vec![Method::Options];
+            for route in &self.routes
+            {
+                if !allowed_methods.contains(&route.method)
+                {
+                    allowed_methods.push(route.method);
+                }
+            }
+
+            let mut response = HttpResponse::new(StatusCode::Ok);
+            response.add_header("Allow", &allow(allowed_methods));
+            return response;
+        }
+
+        let mut path = match request.uri().to_str()
+        {
+            Some(path) => split_path(path),
+            None => return HttpResponse::not_found(),
+        };
+        if self.normalize_trailing_slash && path.len() > 1 && path.last() == Some(&"")
+        {
+            path.pop();
+        }
+        // The methods registered for the path, in the order they were registered.
+        let mut allowed_methods: Vec<Method> = Vec::new();
+        // The GET route for the path, which handles HEAD requests without a route of their own.
+        let mut get_route = None;
+
+        for route in &self.routes
+        {
+            if let Some(params) = route.matches(&path, self.normalize_trailing_slash)
+            {
+                if route.method == request.method()
+                {
+                    return (route.handler)(request, &params);
+                }
+                if route.method == Method::Get && get_route.is_none()
+                {
+                    get_route = Some((route, params));
+                }
+                if !allowed_methods.contains(&route.method)
+                {
+                    allowed_methods.push(route.method);
+                }
+            }
+        }
+
+        if let Some((route, params)) = get_route.filter(|_| request.method() == Method::Head)
+        {
+            let mut response = (route.handler)(request, &params);
+            response.strip_body();
+            return response;
+        }
+        if allowed_methods.is_empty()
+        {
+            return HttpResponse::not_found();
+        }
+
+        let mut response = HttpResponse::new(StatusCode::MethodNotAllowed);
+        response.add_header("Allow", &allow(allowed_methods));
+        response
+    }
+}
+
+/// Respond to a conditional GET of a resource. If the client's copy of the resource, according
+/// to the request's `If-Modified-Since` header, is as recent as the resource, a `304 Not
+/// Modified` is sent without calling the handler. Otherwise the handler produces the response.
+/// Either way the response has a `Last-Modified` header.
+///
+/// # Parameters
+///
+/// - `request`: a reference to the request for the resource.
+/// - `last_modified`: the time the resource was last modified.
+/// - `handler`: the closure that produces the response when the resource has been modified.
+///
+/// # Returns
+///
+/// A `304 Not Modified` response, or the response produced by the handler.
+pub fn conditional_get<F>(request: &HttpRequest, last_modified: SystemTime, handler: F) -> HttpResponse
+where
+    F: FnOnce() -> HttpResponse,
+{
+    let last_modified_header = httpdate::fmt_http_date(last_modified);
+    let conditional = matches!(request.method(), Method::Get | Method::Head);
+
+    if let Some(if_modified_since) = request.if_modified_since().filter(|_| conditional)
+    {
+        // HTTP dates only have a precision of seconds.
+        let seconds = |time: SystemTime| time.duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0);
+        if seconds(last_modified) <= seconds(if_modified_since)
+        {
+            let mut response = HttpResponse::new(StatusCode::NotModified);
+            response.add_header("Last-Modified", &last_modified_header);
+            return response;
+        }
+    }
+
+    let mut response = handler();
+    if response.header("Last-Modified").is_none()
+    {
+        response.add_header("Last-Modified", &last_modified_header);
+    }
+    response
+}
+
+/// Tag a successful response to a GET or HEAD request with an `ETag` computed from its body, see
+/// `response::etag()`, unless the handler has tagged it already. If a tag in the request's
+/// `If-None-Match` header matches, i.e. the client's copy is current, a `304 Not Modified` is
+/// sent instead, without the body. Weak tags match their strong counterparts.
+///
+/// # Parameters
+///
+/// - `request`: a reference to the request for the resource.
+/// - `response`: the response produced by the handler.
+///
+/// # Returns
+///
+/// A `304 Not Modified` response, or the response with its `ETag` header.
+pub fn with_etag(request: &HttpRequest, mut response: HttpResponse) -> HttpResponse
+{
+    let conditional = matches!(request.method(), Method::Get | Method::Head);
+    if !conditional || response.status().code() / 100 != 2
+    {
+        return response;
+    }
+
+    let etag = match (response.header("ETag"), response.body())
+    {
+        (Some(etag), _) => String::from(etag),
+        (None, Some(body)) => {
+            let etag = response::etag(body);
+            response.add_header("ETag", &etag);
+            etag
+        },
+        (None, None) => return response,
+    };
+
+    let weak = |tag: &str| String::from(tag.strip_prefix("W/").unwrap_or(tag));
+    if request.if_none_match().iter().any(|&tag| tag == "*" || weak(tag) == weak(&etag))
+    {
+        let mut response = HttpResponse::new(StatusCode::NotModified);
+        response.add_header("ETag", &etag);
+        return response;
+    }
+
+    response
+}
+
+/// Build the value of an `Allow` header listing methods. `HEAD` is listed after `GET` since GET
+/// routes handle HEAD requests too.
+fn allow(mut methods: Vec<Method>) -> String
+{
+    if let Some(index) = methods.iter().position(|&method| method == Method::Get)
+    {
+        if !methods.contains(&Method::Head)
+        {
+            methods.insert(index + 1, Method::Head);
+        }
+    }
+
+    let methods: Vec<&str> = methods.iter().map(Method::as_str).collect();
+    methods.join(", ")
+}
+
+/// Split a path into the segments following its leading `/`. A trailing `/` results in a
+/// trailing empty segment, so `/chats` and `/chats/` are different paths unless the router
+/// normalizes trailing slashes.
+fn split_path(path: &str) -> Vec<&str>
+{
+    path.split('/').skip(1).collect()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::http::parse_request;
+    use crate::models::{self, Chat};
+
+    /// Build a router with a few routes for chats and messages.
+    fn chat_router() -> Router
+    {
+        let mut router = Router::new();
+        router.add(Method::Get, "/chats", |_, _| HttpResponse::ok("all chats"));
+        router.add(Method::Get, "/chats/:id", |_, params| {
+            HttpResponse::ok(format!("chat {}", params.get("id").unwrap()))
+        });
+        router.add(Method::Get, "/chats/:id/messages", |request, params| {
+            HttpResponse::ok(format!("{} messages of chat {}", request.method(), params.get("id").unwrap()))
+        });
+        router
+    }
+
+    /// Verify that the `route()` function dispatches requests to the handler registered for
+    /// their method and path.
+    #[test]
+    fn test_route_match()
+    {
+        let router = chat_router();
+
+        let mut request = parse_request("GET /chats HTTP/1.1\r\n").unwrap();
+        let mut response = router.route(&request);
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert_eq!(response.body(), Some(&b"all chats"[..]));
+
+        // Test that the value of a parameter segment is captured.
+        request = parse_request("GET /chats/34 HTTP/1.1\r\n").unwrap();
+        response = router.route(&request);
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert_eq!(response.body(), Some(&b"chat 34"[..]));
+
+        request = parse_request("GET /chats/34/messages?limit=20 HTTP/1.1\r\n").unwrap();
+        response = router.route(&request);
+        assert_eq!(response.body(), Some(&b"GET messages of chat 34"[..]));
+    }
+
+    /// Verify that the `route()` function responds with `404 Not Found` for unregistered paths.
+    #[test]
+    fn test_route_not_found()
+    {
+        let router = chat_router();
+
+        let paths = ["/", "/messages", "/chats/", "/chats/34/members", "/chats/34/messages/1"];
+        for path in paths.iter()
+        {
+            let raw_request = format!("GET {} HTTP/1.1\r\n", path);
+            let request = parse_request(&raw_request).unwrap();
+            assert_eq!(router.route(&request).status(), StatusCode::NotFound);
+        }
+
+        // Test that a router without any routes doesn't match anything.
+        let request = parse_request("GET /chats HTTP/1.1\r\n").unwrap();
+        assert_eq!(Router::new().route(&request).status(), StatusCode::NotFound);
+    }
+
+    /// Verify that the `route()` function responds with `405 Method Not Allowed` when the path
+    /// matches a route registered for a different method.
+    #[test]
+    fn test_route_method_not_allowed()
+    {
+        let router = chat_router();
+
+        let mut request = parse_request("DELETE /chats/34 HTTP/1.1\r\n").unwrap();
+        assert_eq!(router.route(&request).status(), StatusCode::MethodNotAllowed);
+
+        request = parse_request("POST /chats HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}").unwrap();
+        assert_eq!(router.route(&request).status(), StatusCode::MethodNotAllowed);
+    }
+
+    /// Verify that a `405 Method Not Allowed` response lists the methods registered for the path
+    /// in its `Allow` header.
+    #[test]
+    fn test_route_method_not_allowed_allow_header()
+    {
+        let mut router = chat_router();
+        router.add(Method::Post, "/chats", |_, _| HttpResponse::new(StatusCode::Created));
+        router.add(Method::Get, "/chats", |_, _| HttpResponse::ok("all chats, again"));
+
+        let mut request = parse_request("DELETE /chats HTTP/1.1\r\n").unwrap();
+        let mut response = router.route(&request);
+        assert_eq!(response.status(), StatusCode::MethodNotAllowed);
+        assert_eq!(response.header("Allow"), Some("GET, HEAD, POST"));
+
+        // Test that only the methods of routes matching the path are listed.
+        request = parse_request("DELETE /chats/34 HTTP/1.1\r\n").unwrap();
+        response = router.route(&request);
+        assert_eq!(response.header("Allow"), Some("GET, HEAD"));
+    }
+
+    /// Verify that the `conditional_get()` function only calls the handler when the resource
+    /// was modified after the request's `If-Modified-Since` time.
+    #[test]
+    fn test_conditional_get()
+    {
+        // The resource was last modified at Sun, 06 Nov 1994 08:49:37 GMT.
+        let last_modified = UNIX_EPOCH + std::time::Duration::from_millis(784_111_777_250);
+        let handler = || HttpResponse::ok("all chats");
+
+        // Test that a client with a copy from after the modification gets a 304.
+        let mut request = parse_request("GET /chats HTTP/1.1\r\nIf-Modified-Since: Mon, 07 Nov 1994 08:49:37 GMT\r\n\r\n").unwrap();
+        let mut response = conditional_get(&request, last_modified, handler);
+        assert_eq!(response.status(), StatusCode::NotModified);
+        assert_eq!(response.body(), None);
+        assert_eq!(response.header("Last-Modified"), Some("Sun, 06 Nov 1994 08:49:37 GMT"));
+
+        // Test that a copy from the same second as the modification is current.
+        request = parse_request("GET /chats HTTP/1.1\r\nIf-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n").unwrap();
+        response = conditional_get(&request, last_modified, handler);
+        assert_eq!(response.status(), StatusCode::NotModified);
+
+        // Test that a client with a copy from before the modification gets the resource.
+        request = parse_request("GET /chats HTTP/1.1\r\nIf-Modified-Since: Sat, 05 Nov 1994 08:49:37 GMT\r\n\r\n").unwrap();
+        response = conditional_get(&request, last_modified, handler);
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert_eq!(response.body(), Some(&b"all chats"[..]));
+        assert_eq!(response.header("Last-Modified"), Some("Sun, 06 Nov 1994 08:49:37 GMT"));
+
+        // Test that the resource is sent when the header is absent or unparseable.
+        let requests = [
+            "GET /chats HTTP/1.1\r\n",
+            "GET /chats HTTP/1.1\r\nIf-Modified-Since: tomorrow\r\n\r\n",
+        ];
+        for raw_request in requests.iter()
+        {
+            request = parse_request(raw_request).unwrap();
+            assert_eq!(conditional_get(&request, last_modified, handler).status(), StatusCode::Ok);
+        }
+    }
+
+    /// Verify that the `add_health_checks()` function registers a liveness route and a readiness
+    /// route driven by the predicate.
+    #[test]
+    fn test_add_health_checks()
+    {
+        let ready = Arc::new(AtomicBool::new(false));
+        let mut router = chat_router();
+        let predicate = Arc::clone(&ready);
+        router.add_health_checks(move || predicate.load(Ordering::SeqCst));
+
+        let liveness = parse_request("GET /healthz HTTP/1.1\r\n").unwrap();
+        let readiness = parse_request("GET /readyz HTTP/1.1\r\n").unwrap();
+
+        // Test that the server is live but not ready until the predicate is true.
+        assert_eq!(router.route(&liveness).status(), StatusCode::Ok);
+        assert_eq!(router.route(&readiness).status(), StatusCode::ServiceUnavailable);
+
+        ready.store(true, Ordering::SeqCst);
+        assert_eq!(router.route(&liveness).status(), StatusCode::Ok);
+        assert_eq!(router.route(&readiness).status(), StatusCode::Ok);
+
+        // Test that the other routes are unaffected.
+        let request = parse_request("GET /chats HTTP/1.1\r\n").unwrap();
+        assert_eq!(router.route(&request).body(), Some(&b"all chats"[..]));
+    }
+
+    /// Verify that the `route()` function answers an `OPTIONS *` request with every method the
+    /// router handles.
+    #[test]
+    fn test_route_options_asterisk()
+    {
+        let mut router = chat_router();
+        router.add(Method::Post, "/chats", |_, _| HttpResponse::new(StatusCode::Created));
+        router.add(Method::Delete, "/chats/:id", |_, _| HttpResponse::new(StatusCode::NoContent));
+
+        let mut request = parse_request("OPTIONS * HTTP/1.1\r\n").unwrap();
+        let mut response = router.route(&request);
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert_eq!(response.header("Allow"), Some("OPTIONS, GET, HEAD, POST, DELETE"));
+
+        // Test that an OPTIONS request for a resource is still routed by its path.
+        request = parse_request("OPTIONS /chats HTTP/1.1\r\n").unwrap();
+        response = router.route(&request);
+        assert_eq!(response.status(), StatusCode::MethodNotAllowed);
+        assert_eq!(response.header("Allow"), Some("GET, HEAD, POST"));
+    }
+
+    /// Verify that the `route()` function handles a `HEAD` request with the GET route for its
+    /// path, without the body but with the same headers.
+    #[test]
+    fn test_route_head()
+    {
+        let mut router = chat_router();
+        router.add(Method::Get, "/messages", |_, _| {
+            HttpResponse::builder().header("Content-Type", "text/plain").body("all messages").build()
+        });
+
+        let mut request = parse_request("GET /messages HTTP/1.1\r\n").unwrap();
+        let get = router.route(&request);
+        request = parse_request("HEAD /messages HTTP/1.1\r\n").unwrap();
+        let mut head = router.route(&request);
+        assert_eq!(head.status(), StatusCode::Ok);
+        assert_eq!(head.body(), None);
+        assert_eq!(head.headers(), &[
+            (String::from("Content-Type"), String::from("text/plain")),
+            (String::from("Content-Length"), String::from("12")),
+        ]);
+        assert_eq!(get.header("Content-Type"), head.header("Content-Type"));
+
+        // Test that a route registered for HEAD takes precedence over the GET route.
+        router.add(Method::Head, "/chats", |_, _| HttpResponse::new(StatusCode::NoContent));
+        request = parse_request("HEAD /chats HTTP/1.1\r\n").unwrap();
+        assert_eq!(router.route(&request).status(), StatusCode::NoContent);
+
+        // Test that a HEAD request for a path without a GET route isn't allowed.
+        router.add(Method::Post, "/messages/:id", |_, _| HttpResponse::new(StatusCode::Created));
+        request = parse_request("HEAD /messages/1 HTTP/1.1\r\n").unwrap();
+        head = router.route(&request);
+        assert_eq!(head.status(), StatusCode::MethodNotAllowed);
+        assert_eq!(head.header("Allow"), Some("POST"));
+    }
+
+    /// Verify that the `normalize_trailing_slash()` option makes paths with and without a
+    /// trailing `/` the same path.
+    #[test]
+    fn test_route_normalize_trailing_slash()
+    {
+        let mut router = chat_router().normalize_trailing_slash(true);
+        router.add(Method::Get, "/messages/", |_, _| HttpResponse::ok("all messages"));
+        router.add(Method::Get, "/", |_, _| HttpResponse::ok("root"));
+
+        // Test that both forms reach the same handler, whichever form it was registered with.
+        let paths = [
+            ("/chats", "all chats"),
+            ("/chats/", "all chats"),
+            ("/chats/34/", "chat 34"),
+            ("/messages", "all messages"),
+            ("/messages/", "all messages"),
+            ("/", "root"),
+        ];
+        for (path, body) in paths.iter()
+        {
+            let raw_request = format!("GET {} HTTP/1.1\r\n", path);
+            let request = parse_request(&raw_request).unwrap();
+            assert_eq!(router.route(&request).body(), Some(body.as_bytes()), "{}", path);
+        }
+
+        // Test that only a single trailing slash is ignored.
+        let request = parse_request("GET /chats/34/messages/1/ HTTP/1.1\r\n").unwrap();
+        assert_eq!(router.route(&request).status(), StatusCode::NotFound);
+
+        // Test that the forms are different paths without the option.
+        router = router.normalize_trailing_slash(false);
+        let mut request = parse_request("GET /chats/ HTTP/1.1\r\n").unwrap();
+        assert_eq!(router.route(&request).status(), StatusCode::NotFound);
+
+        request = parse_request("GET /messages HTTP/1.1\r\n").unwrap();
+        assert_eq!(router.route(&request).status(), StatusCode::NotFound);
+
+        request = parse_request("GET /messages/ HTTP/1.1\r\n").unwrap();
+        assert_eq!(router.route(&request).body(), Some(&b"all messages"[..]));
+    }
+
+    /// Verify that the `with_etag()` function tags responses, and answers requests for a
+    /// current copy with a `304 Not Modified`.
+    #[test]
+    fn test_with_etag()
+    {
+        let body = r#"[{"id":34,"participantIds":[3423,9813]}]"#;
+        let etag = response::etag(body.as_bytes());
+
+        // Test that identical bodies get the same tag.
+        let mut request = parse_request("GET /chats HTTP/1.1\r\n").unwrap();
+        let mut response = with_etag(&request, HttpResponse::ok(body));
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert_eq!(response.header("ETag"), Some(etag.as_str()));
+        assert_eq!(with_etag(&request, HttpResponse::ok(body)), response);
+
+        // Test that a client with a matching tag gets a 304 without the body.
+        let requests = [
+            format!("GET /chats HTTP/1.1\r\nIf-None-Match: {}\r\n\r\n", etag),
+            format!("GET /chats HTTP/1.1\r\nIf-None-Match: \"0\", W/{}\r\n\r\n", etag),
+            String::from("HEAD /chats HTTP/1.1\r\nIf-None-Match: *\r\n\r\n"),
+        ];
+        for raw_request in requests.iter()
+        {
+            request = parse_request(raw_request).unwrap();
+            response = with_etag(&request, HttpResponse::ok(body));
+            assert_eq!(response.status(), StatusCode::NotModified, "{}", raw_request);
+            assert_eq!(response.body(), None);
+            assert_eq!(response.header("ETag"), Some(etag.as_str()));
+        }
+
+        // Test that a tag set by the handler is kept, and compared.
+        request = parse_request("GET /chats HTTP/1.1\r\nIf-None-Match: \"v1\"\r\n\r\n").unwrap();
+        response = with_etag(&request, HttpResponse::builder().header("ETag", "\"v1\"").body(body).build());
+        assert_eq!(response.status(), StatusCode::NotModified);
+
+        // Test that a stale tag gets the resource.
+        request = parse_request("GET /chats HTTP/1.1\r\nIf-None-Match: \"0\"\r\n\r\n").unwrap();
+        assert_eq!(with_etag(&request, HttpResponse::ok(body)).status(), StatusCode::Ok);
+
+        // Test that other methods, failures and responses without a body aren't tagged.
+        request = parse_request("DELETE /chats/34 HTTP/1.1\r\nIf-None-Match: *\r\n\r\n").unwrap();
+        assert_eq!(with_etag(&request, HttpResponse::ok(body)).header("ETag"), None);
+
+        request = parse_request("GET /chats HTTP/1.1\r\nIf-None-Match: *\r\n\r\n").unwrap();
+        assert_eq!(with_etag(&request, HttpResponse::not_found()), HttpResponse::not_found());
+        assert_eq!(with_etag(&request, HttpResponse::new(StatusCode::NoContent)).status(), StatusCode::NoContent);
+    }
+
+    /// Verify that a route creating a chat can answer with a `201 Created` locating the chat.
+    #[test]
+    fn test_route_created()
+    {
+        let store = Arc::new(ChatStore::new());
+        let mut router = Router::new();
+        let chats = Arc::clone(&store);
+        router.add(Method::Post, "/chats", move |request, _| {
+            let id = match request.parse_body_as_chat()
+            {
+                Ok(chat) => match chats.create_chat(chat)
+                {
+                    Ok(id) => id,
+                    Err(_) => return HttpResponse::new(StatusCode::Conflict),
+                },
+                Err(error) => return HttpResponse::from(error),
+            };
+            let chat = chats.get_chat(id).unwrap();
+            HttpResponse::created(&format!("/chats/{}", id), models::serialize_chat(&chat).unwrap())
+        });
+
+        let request = parse_request(
+            "POST /chats HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: 30\r\n\r\n{\"participantIds\":[3423,9813]}"
+        ).unwrap();
+        let response = router.route(&request);
+        assert_eq!(response.status(), StatusCode::Created);
+        assert_eq!(response.header("Location"), Some("/chats/1"));
+        assert_eq!(response.body(), Some(&br#"{"id":1,"participantIds":[3423,9813]}"#[..]));
+        assert_eq!(store.get_chat(1), Some(Chat { id: Some(1), participant_ids: [3423, 9813] }));
+    }
+
+    /// Verify that the `GET /users/:id/chats` route lists the chats a user participates in.
+    #[test]
+    fn test_add_user_chats()
+    {
+        let store = Arc::new(ChatStore::new());
+        store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+        store.create_chat(Chat { id: None, participant_ids: [9837, 3423] }).unwrap();
+        store.create_chat(Chat { id: None, participant_ids: [9837, 1983] }).unwrap();
+        let mut router = Router::new();
+        router.add_user_chats(Arc::clone(&store));
+
+        let mut request = parse_request("GET /users/3423/chats HTTP/1.1\r\n").unwrap();
+        let mut response = router.route(&request);
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert_eq!(response.header("Content-Type"), Some("application/json"));
+        assert_eq!(
+            response.body(),
+            Some(&br#"{"data":[{"id":1,"participantIds":[3423,9813]},{"id":2,"participantIds":[9837,3423]}],"count":2}"#[..])
+        );
+
+        // Test that a user without any chats gets an empty list rather than a 404.
+        request = parse_request("GET /users/42/chats HTTP/1.1\r\n").unwrap();
+        response = router.route(&request);
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert_eq!(response.body(), Some(&br#"{"data":[],"count":0}"#[..]));
+
+        // Test that an id that isn't a number is rejected.
+        request = parse_request("GET /users/me/chats HTTP/1.1\r\n").unwrap();
+        assert_eq!(router.route(&request).status(), StatusCode::BadRequest);
+        request = parse_request("GET /users/-1/chats HTTP/1.1\r\n").unwrap();
+        assert_eq!(router.route(&request).status(), StatusCode::BadRequest);
+    }
+}
\ No newline at end of file
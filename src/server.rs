@@ -0,0 +1,723 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, TrySendError};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use uuid::Uuid;
+
+use crate::http::{self, HttpVersion, Method, ParseError, ParseOptions};
+use crate::middleware::{self, Middleware};
+use crate::response::{HttpResponse, StatusCode};
+
+/// The number of worker threads `serve()` handles connections with.
+pub const DEFAULT_WORKERS: usize = 4;
+
+/// How long a server waits by default for a client to send the next part of a request, or to
+/// start sending its next request, before closing the connection.
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long a server waits by default for a client to accept the next part of a response before
+/// closing the connection.
+pub const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The number of accepted connections that can wait for a free worker by default before new
+/// connections are turned away.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 64;
+
+/// How long a client that's turned away because the server is overloaded is told to wait by
+/// default before trying again.
+pub const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// The maximum number of bytes the body of a request may contain by default. Larger requests
+/// are answered with a `413 Payload Too Large`.
+pub const DEFAULT_MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// The longest `X-Request-Id` a client can send that's used as the id of its request. Longer
+/// ids are replaced with a generated one.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// How often a server that's waiting for a connection or request checks whether it's been shut
+/// down.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Serve HTTP requests on an address, responding to each request with the response the handler
+/// returns. Connections are handled concurrently by `DEFAULT_WORKERS` worker threads and are
+/// kept open for as long as the client asks for them to be. Requests that can't be parsed are
+/// answered with a `400 Bad Request`. The handler can look up the address of the client through
+/// `HttpRequest::peer_addr()`.
+///
+/// # Parameters
+///
+/// - `addr`: the address to listen on, e.g. `"127.0.0.1:8080"`.
+/// - `handler`: the function that produces the response to each request.
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `OK`: Never, the server runs until it fails.
+/// - `Err`: An `io::Error` if the address can't be bound.
+pub fn serve<H>(addr: &str, handler: H) -> io::Result<()>
+where
+    H: Fn(&http::HttpRequest) -> HttpResponse + Send + Sync + 'static,
+{
+    Server::bind(addr, DEFAULT_WORKERS)?.run(handler)
+}
+
+/// The signature of the callbacks that observe each request a server handles, along with the
+/// response it was sent and how long handling it took.
+pub type ResponseCallback = Box<dyn Fn(&http::HttpRequest, &HttpResponse, Duration) + Send + Sync>;
+
+/// Format a request and its response as a line of the Common Log Format that web servers write
+/// access logs in, e.g.
+/// `127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /chats HTTP/1.1" 200 9`.
+///
+/// # Parameters
+///
+/// - `request`: a reference to the request that was handled.
+/// - `response`: a reference to the response it was sent.
+/// - `time`: the time the request was handled at, which is written in UTC.
+///
+/// # Returns
+///
+/// The log line, without a line terminator. The client's address and the size of the body are
+/// `-` if the request doesn't have one or the response doesn't have a body.
+pub fn common_log_format(request: &http::HttpRequest, response: &HttpResponse, time: SystemTime) -> String
+{
+    let host = request.peer_addr().map_or_else(|| String::from("-"), |peer_addr| peer_addr.ip().to_string());
+    // An IMF-fixdate, e.g. `Tue, 10 Oct 2000 13:55:36 GMT`, has the same fields in another order.
+    let date = httpdate::fmt_http_date(time);
+    let fields: Vec<&str> = date.split(' ').collect();
+    let bytes = match response.body()
+    {
+        Some(body) if !body.is_empty() && response.status().allows_body() => body.len().to_string(),
+        _ => String::from("-"),
+    };
+
+    format!(
+        "{} - - [{}/{}/{}:{} +0000] \"{} {} {}\" {} {}",
+        host,
+        fields[1],
+        fields[2],
+        fields[3],
+        fields[4],
+        request.method(),
+        request.raw_target(),
+        request.version(),
+        response.status().code(),
+        bytes
+    )
+}
+
+/// Log a request that was handled in the Common Log Format. See `common_log_format()`. Pass it
+/// to `Server::on_response()` to write an access log.
+pub fn log_common_format(request: &http::HttpRequest, response: &HttpResponse, _elapsed: Duration)
+{
+    log::info!("{}", common_log_format(request, response, SystemTime::now()));
+}
+
+/// A HTTP server that dispatches the connections it accepts to a fixed-size pool of worker
+/// threads, so a slow handler doesn't block other clients.
+pub struct Server
+{
+    listener: TcpListener,
+    workers: usize,
+    on_response: ResponseCallback,
+    middleware: Vec<Box<dyn Middleware>>,
+    shutdown: Arc<AtomicBool>,
+    settings: ConnectionSettings,
+    queue_capacity: usize,
+    retry_after: Duration,
+    stats: Arc<ServerStats>,
+}
+
+/// The settings every connection a server accepts is handled with.
+#[derive(Debug, Clone)]
+struct ConnectionSettings
+{
+    /// How long to wait for a client to send the next part of a request.
+    read_timeout: Duration,
+    /// How long to wait for a client to accept the next part of a response.
+    write_timeout: Duration,
+    /// The name sent in the `Server` header of every response, if any.
+    server_name: Option<String>,
+    /// Whether every request is assigned an id that's echoed in its response's `X-Request-Id`.
+    request_ids: bool,
+    /// The options requests are parsed with.
+    parse_options: ParseOptions,
+}
+
+impl Server
+{
+    /// Bind a server to an address without accepting any connections yet.
+    ///
+    /// # Parameters
+    ///
+    /// - `addr`: the address to listen on, e.g. `"127.0.0.1:8080"`. Port `0` binds to any free
+    ///   port, which can be looked up with `local_addr()`.
+    /// - `workers`: the number of worker threads to handle connections with.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `OK`: The bound `Server`.
+    /// - `Err`: An `io::Error` if the address can't be bound or `workers` is zero.
+    pub fn bind(addr: &str, workers: usize) -> io::Result<Server>
+    {
+        if workers == 0
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "A server needs at least one worker!"));
+        }
+
+        Ok(
+            Server
+            {
+                listener: TcpListener::bind(addr)?,
+                workers,
+                on_response: Box::new(|_, _, _| {}),
+                middleware: Vec::new(),
+                shutdown: Arc::new(AtomicBool::new(false)),
+                settings: ConnectionSettings
+                {
+                    read_timeout: DEFAULT_READ_TIMEOUT,
+                    write_timeout: DEFAULT_WRITE_TIMEOUT,
+                    server_name: None,
+                    request_ids: false,
+                    parse_options: ParseOptions
+                    {
+                        max_body_bytes: Some(DEFAULT_MAX_BODY_BYTES),
+                        ..ParseOptions::default()
+                    },
+                },
+                queue_capacity: DEFAULT_QUEUE_CAPACITY,
+                retry_after: DEFAULT_RETRY_AFTER,
+                stats: Arc::new(ServerStats::default()),
+            }
+        )
+    }
+
+    /// Register a callback that's invoked after each request is responded to, e.g. to log
+    /// requests or record metrics. The callback is passed the request, the response it was sent
+    /// and the time taken to handle the request and write the response. By default nothing is
+    /// done after a request.
+    pub fn on_response<F>(mut self, callback: F) -> Server
+    where
+        F: Fn(&http::HttpRequest, &HttpResponse, Duration) + Send + Sync + 'static,
+    {
+        self.on_response = Box::new(callback);
+        self
+    }
+
+    /// Wrap the server's handler in a middleware. Requests pass through middleware in the order
+    /// it was added before reaching the handler, and responses pass back through in reverse.
+    pub fn wrap<M>(mut self, middleware: M) -> Server
+    where
+        M: Middleware + 'static,
+    {
+        self.middleware.push(Box::new(middleware));
+        self
+    }
+
+    /// Set how long the server waits for a client to send the next part of a request, which
+    /// must not be zero. A client that stalls partway through a request is sent a `408 Request
+    /// Timeout`, and an idle connection is closed once it's been idle this long. The default is
+    /// `DEFAULT_READ_TIMEOUT`.
+    pub fn read_timeout(mut self, timeout: Duration) -> Server
+    {
+        self.settings.read_timeout = timeout;
+        self
+    }
+
+    /// Set how long the server waits for a client to accept the next part of a response, which
+    /// must not be zero. The connection is closed if the client doesn't. The default is
+    /// `DEFAULT_WRITE_TIMEOUT`.
+    pub fn write_timeout(mut self, timeout: Duration) -> Server
+    {
+        self.settings.write_timeout = timeout;
+        self
+    }
+
+    /// Set how many accepted connections can wait for a free worker. Once every worker is busy
+    /// and the queue is full, new connections are answered with a `503 Service Unavailable`
+    /// straight away rather than waiting indefinitely. With a capacity of zero, connections are
+    /// only accepted while a worker is free. The default is `DEFAULT_QUEUE_CAPACITY`.
+    pub fn queue_capacity(mut self, capacity: usize) -> Server
+    {
+        self.queue_capacity = capacity;
+        self
+    }
+
+    /// Set how long the `Retry-After` header of a `503 Service Unavailable` tells a client that's
+    /// turned away to wait, which is rounded up to whole seconds. See `queue_capacity()`. The
+    /// default is `DEFAULT_RETRY_AFTER`.
+    pub fn retry_after(mut self, retry_after: Duration) -> Server
+    {
+        self.retry_after = retry_after;
+        self
+    }
+
+    /// Set the name the server identifies itself with in the `Server` header of every response,
+    /// e.g. `"chatty/0.1"`. By default responses don't have a `Server` header.
+    pub fn server_name(mut self, name: &str) -> Server
+    {
+        self.settings.server_name = Some(String::from(name));
+        self
+    }
+
+    /// Set whether every request is assigned an id to trace it by, e.g. in logs. The id is the
+    /// client's `X-Request-Id` header if it sent a valid one, or a freshly generated UUID
+    /// otherwise. Handlers can look it up through `HttpRequest::request_id()`, and it's echoed
+    /// in the response's `X-Request-Id` header. By default requests aren't assigned ids.
+    pub fn request_ids(mut self, enabled: bool) -> Server
+    {
+        self.settings.request_ids = enabled;
+        self
+    }
+
+    /// Set the options requests are parsed with, e.g. to limit the size of their bodies. A
+    /// request that doesn't meet them is answered with the response of its `ParseError`, before
+    /// the client is told to send its body if it sent `Expect: 100-continue`. The default is
+    /// `ParseOptions::default()` with bodies limited to `DEFAULT_MAX_BODY_BYTES`.
+    pub fn parse_options(mut self, options: ParseOptions) -> Server
+    {
+        self.settings.parse_options = options;
+        self
+    }
+
+    /// Returns the address the server is listening on.
+    pub fn local_addr(&self) -> io::Result<SocketAddr>
+    {
+        self.listener.local_addr()
+    }
+
+    /// Returns a handle that shuts the server down, e.g. from a SIGINT handler. It has to be
+    /// taken before the server is run.
+    pub fn shutdown_handle(&self) -> ShutdownHandle
+    {
+        ShutdownHandle { shutdown: Arc::clone(&self.shutdown) }
+    }
+
+    /// Returns the counters of the connections the server accepts and the responses it sends,
+    /// which keep being updated while the server runs. They have to be taken before the server
+    /// is run.
+    pub fn stats(&self) -> Arc<ServerStats>
+    {
+        Arc::clone(&self.stats)
+    }
+
+    /// Accept connections and dispatch them to the worker threads, which respond to each
+    /// request with the response the handler returns, until the server is shut down with a
+    /// `ShutdownHandle`. See `serve()`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `OK`: The server was shut down and every request in progress has been responded to.
+    /// - `Err`: An `io::Error` if the listener can't be set up to accept connections. Failing to
+    ///   accept a connection only affects that connection, so it's logged and the server goes on.
+    pub fn run<H>(self, handler: H) -> io::Result<()>
+    where
+        H: Fn(&http::HttpRequest) -> HttpResponse + Send + Sync + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel::<TcpStream>(self.queue_capacity);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let stack = self.middleware;
+        let handler = Arc::new(move |request: &http::HttpRequest| middleware::chain(&stack, &handler, request));
+        let on_response = Arc::new(self.on_response);
+
+        let mut workers = Vec::with_capacity(self.workers);
+        for _ in 0 .. self.workers
+        {
+            let receiver = Arc::clone(&receiver);
+            let handler = Arc::clone(&handler);
+            let on_response = Arc::clone(&on_response);
+            let shutdown = Arc::clone(&self.shutdown);
+            let settings = self.settings.clone();
+            let stats = Arc::clone(&self.stats);
+            workers.push(thread::spawn(move || {
+                work(&receiver, handler.as_ref(), on_response.as_ref(), &shutdown, &settings, &stats)
+            }));
+        }
+
+        // Accepting without blocking lets the loop notice when the server is shut down.
+        self.listener.set_nonblocking(true)?;
+        while !self.shutdown.load(Ordering::SeqCst)
+        {
+            match self.listener.accept()
+            {
+                Ok((stream, _)) => {
+                    self.stats.accepted.fetch_add(1, Ordering::Relaxed);
+                    if let Err(error) = stream.set_nonblocking(false)
+                    {
+                        log::warn!("Failed to set up a connection: {}", error);
+                        continue;
+                    }
+                    match sender.try_send(stream)
+                    {
+                        Ok(()) => {},
+                        Err(TrySendError::Full(stream)) => {
+                            if let Err(error) = reject_overloaded(stream, self.retry_after, &self.settings, &self.stats)
+                            {
+                                log::warn!("Failed to turn away a connection: {}", error);
+                            }
+                        },
+                        // The workers only stop once the sender is dropped, so this can't happen.
+                        Err(TrySendError::Disconnected(_)) => panic!("The worker threads have stopped!"),
+                    }
+                },
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => thread::sleep(SHUTDOWN_POLL_INTERVAL),
+                // The accept was interrupted, or the client gave up before it was accepted.
+                Err(error) if matches!(
+                    error.kind(),
+                    io::ErrorKind::Interrupted | io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset
+                ) => {},
+                // E.g. the process is out of file descriptors, which takes connections closing to
+                // fix, so back off rather than spin.
+                Err(error) => {
+                    log::warn!("Failed to accept a connection: {}", error);
+                    thread::sleep(SHUTDOWN_POLL_INTERVAL);
+                },
+            }
+        }
+
+        // Refuse new connections, then wait for the workers to finish the ones they have.
+        drop(self.listener);
+        drop(sender);
+        for worker in workers
+        {
+            // A worker only panics if the logger does, which there's no recovering from.
+            let _ = worker.join();
+        }
+
+        Ok(())
+    }
+}
+
+/// Shuts down the `Server` it was taken from. See `Server::shutdown_handle()`.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle
+{
+    shutdown: Arc<AtomicBool>,
+}
+
+impl ShutdownHandle
+{
+    /// Tell the server to stop accepting connections. Requests that are in progress are still
+    /// responded to, after which their connections are closed. `Server::run()` returns once
+    /// they have been.
+    pub fn shutdown(&self)
+    {
+        self.shutdown.store(true, Ordering::SeqCst);
+    }
+}
+
+/// The counters of the connections a `Server` accepts and the responses it sends. See
+/// `Server::stats()`.
+#[derive(Debug, Default)]
+pub struct ServerStats
+{
+    accepted: AtomicU64,
+    active: AtomicUsize,
+    requests: AtomicU64,
+    client_errors: AtomicU64,
+    server_errors: AtomicU64,
+}
+
+impl ServerStats
+{
+    /// Returns the number of connections the server has accepted, including those it turned
+    /// away because it was overloaded.
+    pub fn accepted(&self) -> u64
+    {
+        self.accepted.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of connections a worker is currently handling.
+    pub fn active(&self) -> usize
+    {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of requests the server has responded to, including those that
+    /// couldn't be parsed.
+    pub fn requests(&self) -> u64
+    {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of responses the server has sent with a `4xx` status code.
+    pub fn client_errors(&self) -> u64
+    {
+        self.client_errors.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of responses the server has sent with a `5xx` status code.
+    pub fn server_errors(&self) -> u64
+    {
+        self.server_errors.load(Ordering::Relaxed)
+    }
+
+    /// Count a response the server is about to send.
+    fn record_response(&self, response: &HttpResponse)
+    {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        match response.status().code()
+        {
+            400 ..= 499 => self.client_errors.fetch_add(1, Ordering::Relaxed),
+            500 ..= 599 => self.server_errors.fetch_add(1, Ordering::Relaxed),
+            _ => 0,
+        };
+    }
+}
+
+/// Handle the connections sent to a worker thread until the server stops sending them. A
+/// handler that panics only drops the connection it was handling, not the worker.
+fn work<H>(
+    receiver: &Mutex<Receiver<TcpStream>>,
+    handler: &H,
+    on_response: &ResponseCallback,
+    shutdown: &AtomicBool,
+    settings: &ConnectionSettings,
+    stats: &ServerStats,
+)
+where
+    H: Fn(&http::HttpRequest) -> HttpResponse,
+{
+    loop
+    {
+        // The lock is released before the connection is handled so other workers can receive.
+        let stream = match receiver.lock().unwrap_or_else(PoisonError::into_inner).recv()
+        {
+            Ok(stream) => stream,
+            Err(_) => return,
+        };
+
+        stats.active.fetch_add(1, Ordering::Relaxed);
+        let handled = panic::catch_unwind(AssertUnwindSafe(|| {
+            handle_connection(stream, handler, on_response, shutdown, settings, stats)
+        }));
+        stats.active.fetch_sub(1, Ordering::Relaxed);
+        match handled
+        {
+            Ok(Ok(())) => {},
+            // An error on one connection shouldn't bring the whole server down.
+            Ok(Err(error)) => log::warn!("Failed to handle connection: {}", error),
+            Err(_) => log::error!("The handler panicked while handling a connection"),
+        }
+    }
+}
+
+/// Read and respond to the requests sent over a connection until the client closes it, asks for
+/// it to be closed, sends a request that can't be parsed, times out or the server is shut down.
+fn handle_connection<H>(
+    stream: TcpStream,
+    handler: &H,
+    on_response: &ResponseCallback,
+    shutdown: &AtomicBool,
+    settings: &ConnectionSettings,
+    stats: &ServerStats,
+) -> io::Result<()>
+where
+    H: Fn(&http::HttpRequest) -> HttpResponse,
+{
+    let peer_addr = stream.peer_addr()?;
+    // The timeout is shared with the clone the responses are written to.
+    stream.set_write_timeout(Some(settings.write_timeout))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let options = &settings.parse_options;
+
+    loop
+    {
+        if !wait_for_request(&mut reader, shutdown, settings.read_timeout)?
+        {
+            return Ok(());
+        }
+
+        let raw_request = match http::read_request_with_continue(&mut reader, options, || write_continue(&mut writer))
+        {
+            Ok(raw_request) => raw_request,
+            // The client closed the connection between requests.
+            Err(ParseError::Io(io::ErrorKind::UnexpectedEof)) => return Ok(()),
+            // The client stopped sending partway through the request.
+            Err(ParseError::Io(io::ErrorKind::WouldBlock)) | Err(ParseError::Io(io::ErrorKind::TimedOut)) => {
+                return write_and_close(&mut writer, HttpResponse::new(StatusCode::RequestTimeout), settings, stats);
+            },
+            Err(ParseError::Io(kind)) => return Err(kind.into()),
+            Err(error) => return write_and_close(&mut writer, HttpResponse::from(error), settings, stats),
+        };
+        let mut request = match http::parse_request_with(&raw_request, options)
+        {
+            Ok(request) => request,
+            Err(error) => return write_and_close(&mut writer, HttpResponse::from(error), settings, stats),
+        };
+
+        request.set_peer_addr(peer_addr);
+        if settings.request_ids
+        {
+            let request_id = request.header("X-Request-Id")
+                .filter(|request_id| is_valid_request_id(request_id))
+                .map_or_else(|| Uuid::new_v4().to_string(), String::from);
+            request.set_request_id(&request_id);
+        }
+        let start = Instant::now();
+
+        let mut response = handler(&request);
+        if let Some(request_id) = request.request_id().filter(|_| response.header("X-Request-Id").is_none())
+        {
+            response.add_header("X-Request-Id", request_id);
+        }
+
+        // Tell the client whether the connection stays open, since HTTP/1.0 clients won't
+        // assume it does. Connections are closed once the server has been shut down.
+        let keep_alive = request.keep_alive() && !shutdown.load(Ordering::SeqCst);
+        if !keep_alive
+        {
+            response.add_header("Connection", "close");
+        }
+        else if request.version() == HttpVersion::Http1_0
+        {
+            response.add_header("Connection", "keep-alive");
+        }
+        // Whatever the handler returned, a response to a HEAD request never has a body.
+        if request.method() == Method::Head
+        {
+            response.strip_body();
+        }
+        add_server_headers(&mut response, settings);
+        stats.record_response(&response);
+
+        writer.write_all(&response.to_bytes())?;
+        writer.flush()?;
+        on_response(&request, &response, start.elapsed());
+
+        if !keep_alive
+        {
+            return Ok(());
+        }
+    }
+}
+
+/// Wait for the client to start sending a request over a connection, checking periodically
+/// whether the server has been shut down so an idle connection doesn't hold the shutdown up.
+///
+/// # Parameters
+///
+/// - `reader`: the reader of the connection.
+/// - `shutdown`: whether the server has been shut down.
+/// - `timeout`: how long the connection can be idle for, which also becomes the timeout of the
+///   reads of the rest of the request.
+///
+/// # Returns
+///
+/// A `Result` which is:
+///
+/// - `OK`: `true` once the client has sent part of a request, or `false` if the client closed
+///   the connection, was idle for too long or the server was shut down first.
+/// - `Err`: An `io::Error` if reading from the connection fails.
+fn wait_for_request(reader: &mut BufReader<TcpStream>, shutdown: &AtomicBool, timeout: Duration) -> io::Result<bool>
+{
+    let start = Instant::now();
+    reader.get_ref().set_read_timeout(Some(SHUTDOWN_POLL_INTERVAL.min(timeout)))?;
+    let started = loop
+    {
+        match reader.fill_buf()
+        {
+            Ok(buffer) => break !buffer.is_empty(),
+            Err(error) if matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                if shutdown.load(Ordering::SeqCst) || start.elapsed() >= timeout
+                {
+                    break false;
+                }
+            },
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => {},
+            Err(error) => return Err(error),
+        }
+    };
+
+    // A client that has started sending a request has to keep sending it.
+    reader.get_ref().set_read_timeout(Some(timeout))?;
+    Ok(started)
+}
+
+/// Tell a client that sent `Expect: 100-continue` to send the body of its request.
+fn write_continue(writer: &mut TcpStream) -> io::Result<()>
+{
+    writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+    writer.flush()
+}
+
+/// Turn away a connection with a `503 Service Unavailable` because every worker is busy and the
+/// queue of connections waiting for one is full. The response is written without reading the
+/// request so the server can get back to accepting connections.
+fn reject_overloaded(
+    mut stream: TcpStream,
+    retry_after: Duration,
+    settings: &ConnectionSettings,
+    stats: &ServerStats,
+) -> io::Result<()>
+{
+    stream.set_write_timeout(Some(settings.write_timeout))?;
+    // Retry-After is in whole seconds, so round up rather than invite an early retry.
+    let retry_after = retry_after.as_secs_f64().ceil() as u64;
+    let response = HttpResponse::builder()
+        .status(StatusCode::ServiceUnavailable)
+        .header("Retry-After", &retry_after.to_string())
+        .build();
+    write_and_close(&mut stream, response, settings, stats)?;
+    stream.shutdown(Shutdown::Write)?;
+
+    // Closing a connection with unread data resets it, which can lose the response, so whatever
+    // the client has sent so far is discarded first.
+    stream.set_nonblocking(true)?;
+    let mut discarded = [0; 4096];
+    while let Ok(read) = stream.read(&mut discarded)
+    {
+        if read == 0
+        {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Respond to a request that couldn't be read or parsed, e.g. with a description of the error.
+/// The connection is closed afterwards since there's no telling where the next request would
+/// start.
+fn write_and_close(writer: &mut TcpStream, mut response: HttpResponse, settings: &ConnectionSettings, stats: &ServerStats)
+    -> io::Result<()>
+{
+    response.add_header("Connection", "close");
+    add_server_headers(&mut response, settings);
+    stats.record_response(&response);
+    writer.write_all(&response.to_bytes())?;
+    writer.flush()
+}
+
+/// Check whether a client's `X-Request-Id` can be used as the id of its request. The id is
+/// written to logs and echoed in the response, so it has to be a short run of visible ASCII.
+fn is_valid_request_id(request_id: &str) -> bool
+{
+    !request_id.is_empty() && request_id.len() <= MAX_REQUEST_ID_LENGTH && request_id.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+/// Add the headers a server sends with every response: the `Date` the response was sent,
+/// unless the handler has set one, and the `Server` header if the server has a name.
+fn add_server_headers(response: &mut HttpResponse, settings: &ConnectionSettings)
+{
+    if response.header("Date").is_none()
+    {
+        response.add_header("Date", &httpdate::fmt_http_date(SystemTime::now()));
+    }
+    if let Some(server_name) = settings.server_name.as_deref().filter(|_| response.header("Server").is_none())
+    {
+        response.add_header("Server", server_name);
+    }
+}
\ No newline at end of file
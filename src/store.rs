@@ -0,0 +1,1065 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::models::{Chat, Message, Timestamp};
+
+/// The most messages `get_messages_paged()` returns at once, whatever limit is asked for.
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// The most messages a `ChatStore` keeps per chat by default.
+pub const DEFAULT_MAX_MESSAGES_PER_CHAT: usize = 10_000;
+
+/// How long a `ChatStore` remembers idempotency keys by default.
+pub const DEFAULT_IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The errors that can occur while modifying a `ChatStore`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoreError
+{
+    /// There's no chat with the given id.
+    UnknownChat { chat_id: u32 },
+    /// A message's source and destination users aren't the two participants of its chat.
+    ParticipantMismatch { chat_id: u32 },
+    /// There's no message with the given id.
+    UnknownMessage { id: String },
+    /// A chat was added with an id that already belongs to another chat.
+    ChatIdTaken { chat_id: u32 },
+    /// The `ChatIdAllocator` has no ids left to allocate.
+    ChatIdsExhausted,
+}
+
+impl fmt::Display for StoreError
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+    {
+        match self
+        {
+            StoreError::UnknownChat { chat_id } => write!(f, "There's no chat with the id {}!", chat_id),
+            StoreError::ParticipantMismatch { chat_id } => {
+                write!(f, "A message must be between the participants of chat {}!", chat_id)
+            },
+            StoreError::UnknownMessage { id } => write!(f, "There's no message with the id {}!", id),
+            StoreError::ChatIdTaken { chat_id } => write!(f, "The id {} already belongs to another chat!", chat_id),
+            StoreError::ChatIdsExhausted => write!(f, "There are no chat ids left to allocate!"),
+        }
+    }
+}
+
+impl Error for StoreError {}
+
+/// Allocates the ids of the chats added to a `ChatStore` without one, e.g. from a counter that's
+/// persisted so ids aren't reused after a restart. The store calls it while it's locked, so ids
+/// are allocated one at a time.
+pub trait ChatIdAllocator: fmt::Debug + Send
+{
+    /// Returns the id of a new chat, or `None` if there are no ids left. The store refuses to add
+    /// a chat under an id another chat already has, so it should be an id that hasn't been used.
+    fn allocate(&mut self) -> Option<u32>;
+
+    /// Record that a chat was added with an id of its own, so the id isn't allocated again. By
+    /// default nothing is recorded.
+    fn reserve(&mut self, _id: u32) {}
+}
+
+/// The default `ChatIdAllocator`, which allocates increasing ids starting at 1. Ids follow the
+/// highest id that's been used, including ids chats were added with, and run out once
+/// `u32::MAX` has been used.
+#[derive(Debug, Clone, Default)]
+pub struct AutoIncrement
+{
+    last_id: u32,
+}
+
+impl ChatIdAllocator for AutoIncrement
+{
+    fn allocate(&mut self) -> Option<u32>
+    {
+        self.last_id = self.last_id.checked_add(1)?;
+        Some(self.last_id)
+    }
+
+    fn reserve(&mut self, id: u32)
+    {
+        self.last_id = self.last_id.max(id);
+    }
+}
+
+/// An in-memory store of chats, safe to share between the threads handling requests.
+#[derive(Debug)]
+pub struct ChatStore
+{
+    state: Mutex<StoreState>,
+    idempotency_window: Duration,
+    max_messages_per_chat: usize,
+}
+
+impl Default for ChatStore
+{
+    fn default() -> ChatStore
+    {
+        ChatStore
+        {
+            state: Mutex::default(),
+            idempotency_window: DEFAULT_IDEMPOTENCY_WINDOW,
+            max_messages_per_chat: DEFAULT_MAX_MESSAGES_PER_CHAT,
+        }
+    }
+}
+
+/// The contents of a `ChatStore`, which are always locked together.
+#[derive(Debug)]
+struct StoreState
+{
+    chats: HashMap<u32, Chat>,
+    // The messages of each chat, ordered by timestamp.
+    messages: HashMap<u32, Vec<Message<'static>>>,
+    // Allocates the ids of chats that are added without one.
+    id_allocator: Box<dyn ChatIdAllocator>,
+    // The idempotency keys messages were added with, and the ids of the messages.
+    idempotency_keys: HashMap<String, IdempotentMessage>,
+}
+
+impl Default for StoreState
+{
+    fn default() -> StoreState
+    {
+        StoreState
+        {
+            chats: HashMap::new(),
+            messages: HashMap::new(),
+            id_allocator: Box::new(AutoIncrement::default()),
+            idempotency_keys: HashMap::new(),
+        }
+    }
+}
+
+/// A message that was added with an idempotency key.
+#[derive(Debug)]
+struct IdempotentMessage
+{
+    id: String,
+    added: Instant,
+}
+
+impl ChatStore
+{
+    /// Create an empty store.
+    pub fn new() -> ChatStore
+    {
+        ChatStore::default()
+    }
+
+    /// Set how long the store remembers idempotency keys. The default is
+    /// `DEFAULT_IDEMPOTENCY_WINDOW`. See `add_message_idempotent()`.
+    pub fn idempotency_window(mut self, window: Duration) -> ChatStore
+    {
+        self.idempotency_window = window;
+        self
+    }
+
+    /// Set the most messages the store keeps per chat, so the store doesn't grow without bound.
+    /// Once a chat has that many, adding a message evicts its oldest message. The default is
+    /// `DEFAULT_MAX_MESSAGES_PER_CHAT`.
+    pub fn max_messages_per_chat(mut self, max: usize) -> ChatStore
+    {
+        self.max_messages_per_chat = max;
+        self
+    }
+
+    /// Set how the ids of chats added without one are allocated. The default is
+    /// `AutoIncrement`.
+    pub fn id_allocator<A>(mut self, allocator: A) -> ChatStore
+    where
+        A: ChatIdAllocator + 'static,
+    {
+        self.state.get_mut().unwrap_or_else(PoisonError::into_inner).id_allocator = Box::new(allocator);
+        self
+    }
+
+    /// Lock the store's contents. A panic while the lock was held can't leave the maps in an
+    /// inconsistent state, so a poisoned lock is recovered rather than propagated.
+    fn lock(&self) -> MutexGuard<'_, StoreState>
+    {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Add a chat to the store. Two users only ever have one chat, so adding a chat between users
+    /// that already have one leaves the store as it is.
+    ///
+    /// # Parameters
+    ///
+    /// - `chat`: the chat to add. A chat without an id is given one by the store's
+    ///   `ChatIdAllocator`.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The id of the chat in the store, which is the id of the existing chat if the users
+    ///   already had one.
+    /// - `Err`: `StoreError::ChatIdTaken` if the chat's id already belongs to a chat between other
+    ///   users, or `StoreError::ChatIdsExhausted` if the chat has no id and none are left.
+    pub fn create_chat(&self, mut chat: Chat) -> Result<u32, StoreError>
+    {
+        let mut state = self.lock();
+        let [first, second] = chat.participant_ids;
+        if let Some(id) = state.chat_between(first, second)
+        {
+            return Ok(id);
+        }
+
+        let id = match chat.id
+        {
+            Some(id) => {
+                if state.chats.contains_key(&id)
+                {
+                    return Err(StoreError::ChatIdTaken { chat_id: id });
+                }
+                state.id_allocator.reserve(id);
+                id
+            },
+            None => state.id_allocator.allocate().ok_or(StoreError::ChatIdsExhausted)?,
+        };
+
+        chat.id = Some(id);
+        state.chats.insert(id, chat);
+
+        Ok(id)
+    }
+
+    /// Look up a chat by id.
+    pub fn get_chat(&self, id: u32) -> Option<Chat>
+    {
+        self.lock().chats.get(&id).cloned()
+    }
+
+    /// Returns every chat a user participates in, ordered by id.
+    pub fn list_chats_for_user(&self, user_id: u32) -> Vec<Chat>
+    {
+        let mut chats: Vec<Chat> = self.lock().chats.values()
+            .filter(|chat| chat.participant_ids.contains(&user_id))
+            .cloned()
+            .collect();
+        chats.sort_by_key(|chat| chat.id);
+
+        chats
+    }
+
+    /// Delete a chat along with its messages.
+    ///
+    /// # Parameters
+    ///
+    /// - `id`: the id of the chat to delete.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The chat and its messages are deleted.
+    /// - `Err`: `StoreError::UnknownChat` if there's no chat with the id.
+    pub fn delete_chat(&self, id: u32) -> Result<(), StoreError>
+    {
+        let mut state = self.lock();
+        state.chats.remove(&id).ok_or(StoreError::UnknownChat { chat_id: id })?;
+        state.messages.remove(&id);
+
+        Ok(())
+    }
+
+    /// Add a message to a chat. Messages with the same timestamp are kept in the order they were
+    /// added. If the chat already has the maximum number of messages, its oldest message is
+    /// evicted, which may be the message being added.
+    ///
+    /// # Parameters
+    ///
+    /// - `chat_id`: the id of the chat the message was sent in.
+    /// - `msg`: the message to add.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The id of the message that was added.
+    /// - `Err`: `StoreError::UnknownChat` if there's no chat with the id, or
+    ///   `StoreError::ParticipantMismatch` if the message isn't from one participant of the
+    ///   chat to the other.
+    pub fn add_message(&self, chat_id: u32, msg: Message) -> Result<String, StoreError>
+    {
+        self.lock().add_message(chat_id, msg, self.max_messages_per_chat)
+    }
+
+    /// Add a message to a chat at most once per idempotency key, e.g. the `Idempotency-Key`
+    /// header of the request, so a client can safely retry a request whose response it didn't
+    /// get. Keys are remembered for the store's idempotency window after the message was added,
+    /// and a message that couldn't be added doesn't use its key up. See `add_message()`.
+    ///
+    /// # Parameters
+    ///
+    /// - `key`: the idempotency key chosen by the client.
+    /// - `chat_id`: the id of the chat the message was sent in.
+    /// - `msg`: the message to add.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The id of the message that was added, or of the message that was added with the
+    ///   key before, in which case this message isn't added.
+    /// - `Err`: The `StoreError` of adding the message.
+    pub fn add_message_idempotent(&self, key: &str, chat_id: u32, msg: Message) -> Result<String, StoreError>
+    {
+        self.add_message_idempotent_at(key, chat_id, msg, Instant::now())
+    }
+
+    /// Add a message at most once per idempotency key as of `now`. See `add_message_idempotent()`.
+    fn add_message_idempotent_at(&self, key: &str, chat_id: u32, msg: Message, now: Instant) -> Result<String, StoreError>
+    {
+        // The lock is held throughout so concurrent retries can't both add the message.
+        let mut state = self.lock();
+        // Expired keys are forgotten so they don't accumulate.
+        let window = self.idempotency_window;
+        state.idempotency_keys.retain(|_, message| now.duration_since(message.added) < window);
+        if let Some(message) = state.idempotency_keys.get(key)
+        {
+            return Ok(message.id.clone());
+        }
+
+        let id = state.add_message(chat_id, msg, self.max_messages_per_chat)?;
+        state.idempotency_keys.insert(String::from(key), IdempotentMessage { id: id.clone(), added: now });
+
+        Ok(id)
+    }
+
+    /// Delete a message. The message is kept so its place in the chat is still shown, but it's
+    /// marked as deleted and its text is removed. Deleting a message again has no effect.
+    ///
+    /// # Parameters
+    ///
+    /// - `id`: the id of the message to delete.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The message is deleted.
+    /// - `Err`: `StoreError::UnknownMessage` if there's no message with the id.
+    pub fn delete_message(&self, id: &str) -> Result<(), StoreError>
+    {
+        let mut state = self.lock();
+        let message = state.messages.values_mut()
+            .flat_map(|messages| messages.iter_mut())
+            .find(|message| message.id == id)
+            .ok_or_else(|| StoreError::UnknownMessage { id: String::from(id) })?;
+
+        message.deleted = true;
+        message.message = Cow::Borrowed("");
+
+        Ok(())
+    }
+
+    /// Mark a message as read by a user at a time. Only the message's recipient can read it, so
+    /// a message marked as read by its sender is left unchanged. A message that's marked as read
+    /// again keeps the time it was first read.
+    ///
+    /// # Parameters
+    ///
+    /// - `message_id`: the id of the message that was read.
+    /// - `reader_id`: the id of the user who read the message.
+    /// - `at`: the time the message was read at.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` which is:
+    ///
+    /// - `Ok`: The message is marked as read, or the reader is its sender.
+    /// - `Err`: `StoreError::UnknownMessage` if there's no message with the id.
+    pub fn mark_read(&self, message_id: &str, reader_id: u32, at: Timestamp) -> Result<(), StoreError>
+    {
+        let mut state = self.lock();
+        let message = state.messages.values_mut()
+            .flat_map(|messages| messages.iter_mut())
+            .find(|message| message.id == message_id)
+            .ok_or_else(|| StoreError::UnknownMessage { id: String::from(message_id) })?;
+
+        if reader_id == message.destination_user_id
+        {
+            message.read_at = Some(message.read_at.map_or(at, |read_at| read_at.min(at)));
+        }
+
+        Ok(())
+    }
+
+    /// Look up a message by its id, in whichever chat it was sent.
+    ///
+    /// # Parameters
+    ///
+    /// - `id`: the id of the message to look up.
+    /// - `include_deleted`: whether to return a message that's been deleted, whose text is
+    ///   blank.
+    ///
+    /// # Returns
+    ///
+    /// An `Option` which is:
+    ///
+    /// - `Some`: The message with the id.
+    /// - `None`: There's no message with the id, or it's been deleted and `include_deleted` is
+    ///   `false`.
+    pub fn get_message(&self, id: &str, include_deleted: bool) -> Option<Message<'static>>
+    {
+        self.lock().messages.values()
+            .flat_map(|messages| messages.iter())
+            .find(|message| message.id == id)
+            .filter(|message| include_deleted || !message.deleted)
+            .cloned()
+    }
+
+    /// Returns the messages of a chat in timestamp order, oldest first. A chat that doesn't
+    /// exist has no messages.
+    pub fn get_messages(&self, chat_id: u32) -> Vec<Message<'static>>
+    {
+        self.lock().messages.get(&chat_id).cloned().unwrap_or_default()
+    }
+
+    /// Returns the conversation between two users, i.e. the messages of the chat they
+    /// participate in, in timestamp order, oldest first. The users can be given in either
+    /// order.
+    ///
+    /// # Parameters
+    ///
+    /// - `user_a`: the id of one of the users.
+    /// - `user_b`: the id of the other user.
+    ///
+    /// # Returns
+    ///
+    /// The messages between the users, which is empty if they don't have a chat.
+    pub fn conversation(&self, user_a: u32, user_b: u32) -> Vec<Message<'static>>
+    {
+        let state = self.lock();
+        state.chat_between(user_a, user_b)
+            .and_then(|id| state.messages.get(&id))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Returns a page of a chat's messages, newest first, for paging back through its history.
+    ///
+    /// # Parameters
+    ///
+    /// - `chat_id`: the id of the chat to list the messages of.
+    /// - `before_timestamp`: only messages strictly older than this are returned, or `None` to
+    ///   start from the newest message.
+    /// - `limit`: the most messages to return, which is capped to `MAX_PAGE_SIZE`.
+    ///
+    /// # Returns
+    ///
+    /// The messages of the page, which is empty once there are no older messages.
+    pub fn get_messages_paged(&self, chat_id: u32, before_timestamp: Option<Timestamp>, limit: usize) -> Vec<Message<'static>>
+    {
+        let state = self.lock();
+        let messages = match state.messages.get(&chat_id)
+        {
+            Some(messages) => messages,
+            None => return Vec::new(),
+        };
+
+        // The messages are in timestamp order, so the older ones are a prefix.
+        let end = match before_timestamp
+        {
+            Some(before) => messages.partition_point(|message| message.timestamp < before),
+            None => messages.len(),
+        };
+        messages[.. end].iter()
+            .rev()
+            .take(limit.min(MAX_PAGE_SIZE))
+            .cloned()
+            .collect()
+    }
+
+    /// Search a chat for the messages whose text contains a query, ignoring case.
+    ///
+    /// # Parameters
+    ///
+    /// - `chat_id`: the id of the chat to search.
+    /// - `query`: the text to search for.
+    ///
+    /// # Returns
+    ///
+    /// The matching messages, newest first. An empty query matches no messages.
+    pub fn search_messages(&self, chat_id: u32, query: &str) -> Vec<Message<'static>>
+    {
+        if query.is_empty()
+        {
+            return Vec::new();
+        }
+
+        let query = query.to_lowercase();
+        let state = self.lock();
+        state.messages.get(&chat_id)
+            .map(|messages| {
+                messages.iter()
+                    .rev()
+                    .filter(|message| message.message.to_lowercase().contains(&query))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl StoreState
+{
+    /// Add a message to a chat. See `ChatStore::add_message()`.
+    fn add_message(&mut self, chat_id: u32, msg: Message, max_messages: usize) -> Result<String, StoreError>
+    {
+        let chat = self.chats.get(&chat_id).ok_or(StoreError::UnknownChat { chat_id })?;
+
+        let [first, second] = chat.participant_ids;
+        let users = (msg.source_user_id, msg.destination_user_id);
+        if users != (first, second) && users != (second, first)
+        {
+            return Err(StoreError::ParticipantMismatch { chat_id });
+        }
+
+        let id = msg.id.clone();
+        let messages = self.messages.entry(chat_id).or_default();
+        let index = messages.partition_point(|message| message.timestamp <= msg.timestamp);
+        messages.insert(index, msg.into_owned());
+        // The messages are in timestamp order, so the oldest ones are a prefix.
+        let excess = messages.len().saturating_sub(max_messages);
+        messages.drain(.. excess);
+
+        Ok(id)
+    }
+
+    /// Returns the id of the chat between two users, whichever order they're given in.
+    fn chat_between(&self, user_a: u32, user_b: u32) -> Option<u32>
+    {
+        self.chats.iter()
+            .find(|(_, chat)| chat.participant_ids == [user_a, user_b] || chat.participant_ids == [user_b, user_a])
+            .map(|(&id, _)| id)
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    /// Build a message between two users with the given timestamp and text.
+    fn message(timestamp: u64, text: &str, source: u32, destination: u32) -> Message<'_>
+    {
+        Message {
+            id: format!("message-{}", timestamp),
+            timestamp: Timestamp::from_millis(timestamp),
+            message: Cow::from(text),
+            source_user_id: source,
+            destination_user_id: destination,
+            deleted: false,
+            read_at: None,
+        }
+    }
+
+    /// Verify that the `create_chat()` function assigns ids to chats that don't have one.
+    #[test]
+    fn test_create_chat()
+    {
+        let store = ChatStore::new();
+
+        // Test that ids are allocated incrementally, starting at 1.
+        let mut id = store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+        assert_eq!(id, 1);
+
+        id = store.create_chat(Chat { id: None, participant_ids: [3423, 1983] }).unwrap();
+        assert_eq!(id, 2);
+
+        // Test that a chat's own id is kept, and allocation continues after it.
+        id = store.create_chat(Chat { id: Some(34), participant_ids: [9837, 1983] }).unwrap();
+        assert_eq!(id, 34);
+
+        id = store.create_chat(Chat { id: None, participant_ids: [9837, 9813] }).unwrap();
+        assert_eq!(id, 35);
+    }
+
+    /// An allocator that hands out the ids it was given, in order.
+    #[derive(Debug)]
+    struct FixedIds(Vec<u32>);
+
+    impl ChatIdAllocator for FixedIds
+    {
+        fn allocate(&mut self) -> Option<u32>
+        {
+            if self.0.is_empty()
+            {
+                return None;
+            }
+            Some(self.0.remove(0))
+        }
+    }
+
+    /// Verify that the `create_chat()` function allocates ids with the store's allocator.
+    #[test]
+    fn test_create_chat_id_allocator()
+    {
+        let store = ChatStore::new().id_allocator(FixedIds(vec![7001, 42]));
+        assert_eq!(store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap(), 7001);
+        assert_eq!(store.create_chat(Chat { id: None, participant_ids: [3423, 1983] }).unwrap(), 42);
+        assert_eq!(store.get_chat(42), Some(Chat { id: Some(42), participant_ids: [3423, 1983] }));
+
+        // Test that a chat's own id is kept without asking the allocator.
+        assert_eq!(store.create_chat(Chat { id: Some(34), participant_ids: [9837, 1983] }).unwrap(), 34);
+        assert_eq!(store.list_chats_for_user(3423).len(), 2);
+    }
+
+    /// Verify that the `create_chat()` function fails rather than reusing ids once the allocator
+    /// has run out.
+    #[test]
+    fn test_create_chat_ids_exhausted()
+    {
+        let store = ChatStore::new();
+        let id = store.create_chat(Chat { id: Some(u32::MAX), participant_ids: [3423, 9813] }).unwrap();
+        assert_eq!(id, u32::MAX);
+
+        let result = store.create_chat(Chat { id: None, participant_ids: [3423, 1983] });
+        assert_eq!(result, Err(StoreError::ChatIdsExhausted));
+        assert_eq!(store.get_chat(u32::MAX), Some(Chat { id: Some(u32::MAX), participant_ids: [3423, 9813] }));
+
+        // Test that a custom allocator can run out too.
+        let store = ChatStore::new().id_allocator(FixedIds(vec![7001]));
+        assert_eq!(store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }), Ok(7001));
+        assert_eq!(store.create_chat(Chat { id: None, participant_ids: [3423, 1983] }), Err(StoreError::ChatIdsExhausted));
+    }
+
+    /// Verify that the `get_chat()` function returns the chat stored under an id.
+    #[test]
+    fn test_get_chat()
+    {
+        let store = ChatStore::new();
+        let id = store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+
+        let expected = Chat { id: Some(id), participant_ids: [3423, 9813] };
+        assert_eq!(store.get_chat(id), Some(expected));
+        assert_eq!(store.get_chat(id + 1), None);
+    }
+
+    /// Verify that the `list_chats_for_user()` function matches a user on either participant.
+    #[test]
+    fn test_list_chats_for_user()
+    {
+        let store = ChatStore::new();
+        store.create_chat(Chat { id: Some(2), participant_ids: [1983, 3423] }).unwrap();
+        store.create_chat(Chat { id: Some(1), participant_ids: [3423, 9813] }).unwrap();
+        store.create_chat(Chat { id: Some(3), participant_ids: [9837, 1983] }).unwrap();
+
+        let mut expected = vec![
+            Chat { id: Some(1), participant_ids: [3423, 9813] },
+            Chat { id: Some(2), participant_ids: [1983, 3423] },
+        ];
+        assert_eq!(store.list_chats_for_user(3423), expected);
+
+        expected = vec![Chat { id: Some(1), participant_ids: [3423, 9813] }];
+        assert_eq!(store.list_chats_for_user(9813), expected);
+
+        expected = vec![
+            Chat { id: Some(2), participant_ids: [1983, 3423] },
+            Chat { id: Some(3), participant_ids: [9837, 1983] },
+        ];
+        assert_eq!(store.list_chats_for_user(1983), expected);
+
+        assert_eq!(store.list_chats_for_user(1), Vec::new());
+    }
+
+    /// Verify that the `get_messages()` function returns a chat's messages in timestamp order.
+    #[test]
+    fn test_get_messages_order()
+    {
+        let store = ChatStore::new();
+        let id = store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+
+        store.add_message(id, message(1572297339, "second", 9813, 3423)).unwrap();
+        store.add_message(id, message(1572297338, "first", 3423, 9813)).unwrap();
+        store.add_message(id, message(1572297340, "third", 3423, 9813)).unwrap();
+        store.add_message(id, message(1572297340, "fourth", 9813, 3423)).unwrap();
+
+        let texts: Vec<String> = store.get_messages(id).iter()
+            .map(|message| message.message.to_string())
+            .collect();
+        assert_eq!(texts, vec!["first", "second", "third", "fourth"]);
+
+        // Test that a chat without messages, or without a chat, has no messages.
+        let other = store.create_chat(Chat { id: None, participant_ids: [9837, 1983] }).unwrap();
+        assert_eq!(store.get_messages(other), Vec::new());
+        assert_eq!(store.get_messages(other + 1), Vec::new());
+    }
+
+    /// Verify that the `add_message()` function rejects messages for chats that don't exist.
+    #[test]
+    fn test_add_message_unknown_chat()
+    {
+        let store = ChatStore::new();
+        let result = store.add_message(34, message(1572297339, "Hello", 3423, 9813));
+        assert_eq!(result, Err(StoreError::UnknownChat { chat_id: 34 }));
+    }
+
+    /// Verify that the `add_message()` function rejects messages that aren't between the
+    /// participants of the chat.
+    #[test]
+    fn test_add_message_participant_mismatch()
+    {
+        let store = ChatStore::new();
+        let id = store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+
+        // Test that an outsider can't send or receive messages in the chat.
+        let mut result = store.add_message(id, message(1572297339, "Hello", 1983, 9813));
+        assert_eq!(result, Err(StoreError::ParticipantMismatch { chat_id: id }));
+
+        result = store.add_message(id, message(1572297339, "Hello", 3423, 1983));
+        assert_eq!(result, Err(StoreError::ParticipantMismatch { chat_id: id }));
+
+        // Test that a participant can't send a message to themselves.
+        result = store.add_message(id, message(1572297339, "Hello", 3423, 3423));
+        assert_eq!(result, Err(StoreError::ParticipantMismatch { chat_id: id }));
+
+        assert_eq!(store.get_messages(id), Vec::new());
+    }
+
+    /// Verify that the `get_messages_paged()` function pages back through a chat's messages,
+    /// newest first.
+    #[test]
+    fn test_get_messages_paged()
+    {
+        let store = ChatStore::new();
+        let id = store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+        for timestamp in 1 ..= 5
+        {
+            store.add_message(id, message(timestamp, "Hello", 3423, 9813)).unwrap();
+        }
+        let timestamps = |page: Vec<Message>| page.iter().map(|message| message.timestamp.as_millis()).collect::<Vec<u64>>();
+
+        // Test that the first page starts from the newest message.
+        assert_eq!(timestamps(store.get_messages_paged(id, None, 2)), vec![5, 4]);
+
+        // Test that only messages strictly older than the timestamp are returned.
+        assert_eq!(timestamps(store.get_messages_paged(id, Some(Timestamp::from_millis(4)), 2)), vec![3, 2]);
+        assert_eq!(timestamps(store.get_messages_paged(id, Some(Timestamp::from_millis(2)), 2)), vec![1]);
+
+        // Test that a limit larger than the number of messages returns all of them.
+        assert_eq!(timestamps(store.get_messages_paged(id, None, 10)), vec![5, 4, 3, 2, 1]);
+        assert_eq!(timestamps(store.get_messages_paged(id, Some(Timestamp::from_millis(6)), 10)), vec![5, 4, 3, 2, 1]);
+    }
+
+    /// Verify that the `get_messages_paged()` function returns empty pages past the oldest
+    /// message, for a limit of zero and for chats without messages.
+    #[test]
+    fn test_get_messages_paged_empty()
+    {
+        let store = ChatStore::new();
+        let id = store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+        assert_eq!(store.get_messages_paged(id, None, 10), Vec::new());
+        assert_eq!(store.get_messages_paged(id + 1, None, 10), Vec::new());
+
+        store.add_message(id, message(1572297339, "Hello", 3423, 9813)).unwrap();
+        assert_eq!(store.get_messages_paged(id, Some(Timestamp::from_millis(1572297339)), 10), Vec::new());
+        assert_eq!(store.get_messages_paged(id, None, 0), Vec::new());
+    }
+
+    /// Verify that the `search_messages()` function finds the messages of a chat that contain a
+    /// query, newest first.
+    #[test]
+    fn test_search_messages()
+    {
+        let store = ChatStore::new();
+        let id = store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+        store.add_message(id, message(1, "Lunch at noon?", 3423, 9813)).unwrap();
+        store.add_message(id, message(2, "Sure, see you then", 9813, 3423)).unwrap();
+        store.add_message(id, message(3, "LUNCH is cancelled", 3423, 9813)).unwrap();
+        let timestamps = |found: Vec<Message>| found.iter().map(|message| message.timestamp.as_millis()).collect::<Vec<u64>>();
+
+        assert_eq!(timestamps(store.search_messages(id, "see you")), vec![2]);
+
+        // Test that the search ignores case and returns the newest match first.
+        assert_eq!(timestamps(store.search_messages(id, "lunch")), vec![3, 1]);
+        assert_eq!(timestamps(store.search_messages(id, "Noon")), vec![1]);
+
+        assert_eq!(store.search_messages(id, "dinner"), Vec::new());
+        assert_eq!(store.search_messages(id + 1, "lunch"), Vec::new());
+
+        // Test that an empty query matches nothing rather than everything.
+        assert_eq!(store.search_messages(id, ""), Vec::new());
+    }
+
+    /// Verify that the `get_messages_paged()` function caps the limit to `MAX_PAGE_SIZE`.
+    #[test]
+    fn test_get_messages_paged_limit_cap()
+    {
+        let store = ChatStore::new();
+        let id = store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+        for timestamp in 0 .. MAX_PAGE_SIZE as u64 + 1
+        {
+            store.add_message(id, message(timestamp, "Hello", 3423, 9813)).unwrap();
+        }
+
+        assert_eq!(store.get_messages_paged(id, None, MAX_PAGE_SIZE).len(), MAX_PAGE_SIZE);
+        assert_eq!(store.get_messages_paged(id, None, 1_000_000).len(), MAX_PAGE_SIZE);
+        assert_eq!(store.get_messages_paged(id, None, usize::MAX).len(), MAX_PAGE_SIZE);
+    }
+
+    /// Verify that the `delete_message()` function blanks a message's text but keeps it in its
+    /// chat.
+    #[test]
+    fn test_delete_message()
+    {
+        let store = ChatStore::new();
+        let id = store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+        store.add_message(id, message(1, "Hello", 3423, 9813)).unwrap();
+        store.add_message(id, message(2, "Secret", 9813, 3423)).unwrap();
+
+        store.delete_message("message-2").unwrap();
+        let mut messages = store.get_messages(id);
+        assert_eq!(messages.len(), 2);
+        assert_eq!((messages[0].message.as_ref(), messages[0].deleted), ("Hello", false));
+        assert_eq!((messages[1].message.as_ref(), messages[1].deleted), ("", true));
+        assert_eq!(messages[1].id, "message-2");
+
+        // Test that deleting a message again has no effect.
+        store.delete_message("message-2").unwrap();
+        assert_eq!(store.get_messages(id), messages);
+
+        messages = store.get_messages_paged(id, None, 1);
+        assert!(messages[0].deleted);
+        assert_eq!(messages[0].message, "");
+    }
+
+    /// Verify that the `delete_message()` function rejects ids of messages that don't exist.
+    #[test]
+    fn test_delete_message_unknown()
+    {
+        let store = ChatStore::new();
+        let result = store.delete_message("message-1");
+        assert_eq!(result, Err(StoreError::UnknownMessage { id: String::from("message-1") }));
+    }
+
+    /// Verify that the `mark_read()` function stamps messages read by their recipient with the
+    /// time they were first read.
+    #[test]
+    fn test_mark_read()
+    {
+        let store = ChatStore::new();
+        let id = store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+        store.add_message(id, message(1, "Hello", 3423, 9813)).unwrap();
+        assert_eq!(store.get_message("message-1", false).unwrap().read_at, None);
+
+        store.mark_read("message-1", 9813, Timestamp::from_millis(1000)).unwrap();
+        assert_eq!(store.get_message("message-1", false).unwrap().read_at, Some(Timestamp::from_millis(1000)));
+
+        // Test that marking the message again keeps the earliest time.
+        store.mark_read("message-1", 9813, Timestamp::from_millis(2000)).unwrap();
+        assert_eq!(store.get_message("message-1", false).unwrap().read_at, Some(Timestamp::from_millis(1000)));
+        store.mark_read("message-1", 9813, Timestamp::from_millis(500)).unwrap();
+        assert_eq!(store.get_message("message-1", false).unwrap().read_at, Some(Timestamp::from_millis(500)));
+
+        // Test that the sender reading their own message has no effect.
+        store.add_message(id, message(2, "Hi", 3423, 9813)).unwrap();
+        store.mark_read("message-2", 3423, Timestamp::from_millis(1000)).unwrap();
+        assert_eq!(store.get_message("message-2", false).unwrap().read_at, None);
+
+        let result = store.mark_read("message-3", 9813, Timestamp::from_millis(1000));
+        assert_eq!(result, Err(StoreError::UnknownMessage { id: String::from("message-3") }));
+    }
+
+    /// Verify that the `get_message()` function looks up messages by id across chats.
+    #[test]
+    fn test_get_message()
+    {
+        let store = ChatStore::new();
+        let first = store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+        let second = store.create_chat(Chat { id: None, participant_ids: [3423, 1234] }).unwrap();
+        assert_eq!(store.add_message(first, message(1, "Hello", 3423, 9813)), Ok(String::from("message-1")));
+        assert_eq!(store.add_message(second, message(2, "Hi", 1234, 3423)), Ok(String::from("message-2")));
+        assert_eq!(store.add_message(second, message(3, "Secret", 3423, 1234)), Ok(String::from("message-3")));
+
+        assert_eq!(store.get_message("message-1", false), Some(message(1, "Hello", 3423, 9813).into_owned()));
+        assert_eq!(store.get_message("message-2", false), Some(message(2, "Hi", 1234, 3423).into_owned()));
+
+        // Test that a deleted message is only returned when asked for.
+        store.delete_message("message-3").unwrap();
+        assert_eq!(store.get_message("message-3", false), None);
+        let deleted = store.get_message("message-3", true).unwrap();
+        assert!(deleted.deleted);
+        assert_eq!(deleted.message, "");
+
+        assert_eq!(store.get_message("message-4", false), None);
+        assert_eq!(store.get_message("message-4", true), None);
+    }
+
+    /// Verify that the `conversation()` function returns the messages between two users,
+    /// whichever order they're given in.
+    #[test]
+    fn test_conversation()
+    {
+        let store = ChatStore::new();
+        let id = store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+        let other = store.create_chat(Chat { id: None, participant_ids: [3423, 1983] }).unwrap();
+        store.add_message(id, message(2, "Hi", 9813, 3423)).unwrap();
+        store.add_message(id, message(1, "Hello", 3423, 9813)).unwrap();
+        store.add_message(other, message(3, "Hey", 1983, 3423)).unwrap();
+
+        let expected = vec![message(1, "Hello", 3423, 9813).into_owned(), message(2, "Hi", 9813, 3423).into_owned()];
+        assert_eq!(store.conversation(3423, 9813), expected);
+        assert_eq!(store.conversation(9813, 3423), expected);
+
+        // Test that users without a chat, or without messages, have an empty conversation.
+        store.create_chat(Chat { id: None, participant_ids: [9813, 1983] }).unwrap();
+        assert_eq!(store.conversation(1983, 9813), Vec::new());
+        assert_eq!(store.conversation(9813, 1234), Vec::new());
+        assert_eq!(store.conversation(3423, 3423), Vec::new());
+    }
+
+    /// Verify that the `create_chat()` function returns the existing chat between two users
+    /// rather than adding another, whichever order the participants are given in.
+    #[test]
+    fn test_create_chat_duplicate()
+    {
+        let store = ChatStore::new();
+        let id = store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+
+        let mut duplicate = store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+        assert_eq!(duplicate, id);
+
+        duplicate = store.create_chat(Chat { id: None, participant_ids: [9813, 3423] }).unwrap();
+        assert_eq!(duplicate, id);
+
+        // Test that the existing chat is kept, even if the duplicate has its own id.
+        duplicate = store.create_chat(Chat { id: Some(34), participant_ids: [9813, 3423] }).unwrap();
+        assert_eq!(duplicate, id);
+        assert_eq!(store.get_chat(id), Some(Chat { id: Some(id), participant_ids: [3423, 9813] }));
+        assert_eq!(store.get_chat(34), None);
+        assert_eq!(store.list_chats_for_user(3423).len(), 1);
+
+        // Test that ids aren't used up by duplicates.
+        assert_eq!(store.create_chat(Chat { id: None, participant_ids: [3423, 1983] }).unwrap(), id + 1);
+    }
+
+    /// Verify that the `create_chat()` function rejects a chat with an id that already belongs to
+    /// a chat between other users.
+    #[test]
+    fn test_create_chat_id_taken()
+    {
+        let store = ChatStore::new();
+        let id = store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+        store.add_message(id, message(1572297339, "Hello", 3423, 9813)).unwrap();
+
+        let result = store.create_chat(Chat { id: Some(id), participant_ids: [9837, 1983] });
+        assert_eq!(result, Err(StoreError::ChatIdTaken { chat_id: id }));
+
+        // Test that the existing chat and its messages are left as they were.
+        assert_eq!(store.get_chat(id), Some(Chat { id: Some(id), participant_ids: [3423, 9813] }));
+        assert_eq!(store.get_messages(id).len(), 1);
+        assert_eq!(store.list_chats_for_user(9837), Vec::new());
+    }
+
+    /// Verify that the `delete_chat()` function deletes a chat along with its messages.
+    #[test]
+    fn test_delete_chat()
+    {
+        let store = ChatStore::new();
+        let id = store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+        let other = store.create_chat(Chat { id: None, participant_ids: [3423, 1983] }).unwrap();
+        store.add_message(id, message(1, "Hello", 3423, 9813)).unwrap();
+        store.add_message(other, message(2, "Hi", 1983, 3423)).unwrap();
+
+        store.delete_chat(id).unwrap();
+        assert_eq!(store.get_chat(id), None);
+        assert_eq!(store.get_messages(id), Vec::new());
+        assert_eq!(store.get_message("message-1", true), None);
+        assert_eq!(store.conversation(3423, 9813), Vec::new());
+
+        // Test that the other chat is untouched.
+        assert_eq!(store.list_chats_for_user(3423), vec![Chat { id: Some(other), participant_ids: [3423, 1983] }]);
+        assert_eq!(store.get_messages(other).len(), 1);
+
+        // Test that deleting it again, or deleting a chat that never existed, fails.
+        assert_eq!(store.delete_chat(id), Err(StoreError::UnknownChat { chat_id: id }));
+        assert_eq!(store.delete_chat(34), Err(StoreError::UnknownChat { chat_id: 34 }));
+    }
+
+    /// Verify that the `add_message_idempotent()` function adds a message once per key.
+    #[test]
+    fn test_add_message_idempotent()
+    {
+        let store = ChatStore::new();
+        let id = store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+
+        // Test that a retry with the same key returns the original message.
+        let mut result = store.add_message_idempotent("key-1", id, message(1, "Hello", 3423, 9813));
+        assert_eq!(result, Ok(String::from("message-1")));
+        result = store.add_message_idempotent("key-1", id, message(2, "Hello", 3423, 9813));
+        assert_eq!(result, Ok(String::from("message-1")));
+        assert_eq!(store.get_messages(id).len(), 1);
+
+        // Test that different keys add different messages.
+        result = store.add_message_idempotent("key-2", id, message(3, "Hello", 3423, 9813));
+        assert_eq!(result, Ok(String::from("message-3")));
+        assert_eq!(store.get_messages(id).len(), 2);
+
+        // Test that a message that couldn't be added doesn't use its key up.
+        result = store.add_message_idempotent("key-3", id + 1, message(4, "Hello", 3423, 9813));
+        assert_eq!(result, Err(StoreError::UnknownChat { chat_id: id + 1 }));
+        result = store.add_message_idempotent("key-3", id, message(4, "Hello", 3423, 9813));
+        assert_eq!(result, Ok(String::from("message-4")));
+        assert_eq!(store.get_messages(id).len(), 3);
+    }
+
+    /// Verify that idempotency keys expire after the store's idempotency window.
+    #[test]
+    fn test_add_message_idempotent_expiry()
+    {
+        let store = ChatStore::new().idempotency_window(Duration::from_secs(60));
+        let id = store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+        let start = Instant::now();
+
+        store.add_message_idempotent_at("key-1", id, message(1, "Hello", 3423, 9813), start).unwrap();
+        let mut now = start + Duration::from_secs(59);
+        let mut result = store.add_message_idempotent_at("key-1", id, message(2, "Hello", 3423, 9813), now);
+        assert_eq!(result, Ok(String::from("message-1")));
+
+        now = start + Duration::from_secs(60);
+        result = store.add_message_idempotent_at("key-1", id, message(3, "Hello", 3423, 9813), now);
+        assert_eq!(result, Ok(String::from("message-3")));
+        assert_eq!(store.get_messages(id).len(), 2);
+        assert_eq!(store.lock().idempotency_keys.len(), 1);
+    }
+
+    /// Verify that the `add_message()` function evicts the oldest messages of a chat that has
+    /// the maximum number of messages.
+    #[test]
+    fn test_add_message_eviction()
+    {
+        let store = ChatStore::new().max_messages_per_chat(3);
+        let id = store.create_chat(Chat { id: None, participant_ids: [3423, 9813] }).unwrap();
+        let other = store.create_chat(Chat { id: None, participant_ids: [3423, 1983] }).unwrap();
+        for timestamp in 1 ..= 5
+        {
+            store.add_message(id, message(timestamp, "Hello", 3423, 9813)).unwrap();
+        }
+        store.add_message(other, message(1, "Hello", 3423, 1983)).unwrap();
+        let timestamps = |chat_id| store.get_messages(chat_id).iter().map(|message| message.timestamp.as_millis()).collect::<Vec<u64>>();
+
+        // Test that the newest messages are kept in order.
+        assert_eq!(timestamps(id), vec![3, 4, 5]);
+        assert_eq!(store.get_message("message-2", true), None);
+
+        // Test that a message older than the ones kept is evicted right away.
+        store.add_message(id, message(2, "Late", 9813, 3423)).unwrap();
+        assert_eq!(timestamps(id), vec![3, 4, 5]);
+
+        store.add_message(id, message(4, "Hi", 9813, 3423)).unwrap();
+        assert_eq!(timestamps(id), vec![4, 4, 5]);
+
+        // Test that other chats have their own limit.
+        assert_eq!(timestamps(other), vec![1]);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,135 @@
+use base64::Engine;
+use sha1::{Digest, Sha1};
+
+use crate::http::HttpRequest;
+use crate::response::{HttpResponse, StatusCode};
+
+/// The version of the WebSocket protocol the server speaks.
+pub const WEBSOCKET_VERSION: &str = "13";
+
+/// The GUID a client's key is appended to when computing the key accepting it (RFC 6455,
+/// section 1.3).
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Compute the `Sec-WebSocket-Accept` value accepting a client's `Sec-WebSocket-Key`, which is
+/// the base64 encoded SHA-1 hash of the key followed by the WebSocket GUID.
+///
+/// # Parameters
+///
+/// - `key`: the value of the client's `Sec-WebSocket-Key` header.
+///
+/// # Returns
+///
+/// The value of the `Sec-WebSocket-Accept` header.
+pub fn accept_key(key: &str) -> String
+{
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(WEBSOCKET_GUID.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(hasher.finalize())
+}
+
+/// Respond to the opening handshake of a WebSocket connection, i.e. a request for which
+/// `HttpRequest::is_websocket_upgrade()` is `true`. Only the handshake is handled, exchanging
+/// frames over the upgraded connection is up to the caller.
+///
+/// # Parameters
+///
+/// - `request`: a reference to the request to upgrade the connection of.
+///
+/// # Returns
+///
+/// A `101 Switching Protocols` response accepting the client's key, a `426 Upgrade Required`
+/// with the supported `Sec-WebSocket-Version` if the client asked for another version, or a
+/// `400 Bad Request` if the request isn't a valid upgrade request.
+pub fn handshake(request: &HttpRequest) -> HttpResponse
+{
+    let key = match request.header("Sec-WebSocket-Key").filter(|_| request.is_websocket_upgrade())
+    {
+        Some(key) => key,
+        None => return HttpResponse::new(StatusCode::BadRequest),
+    };
+    if request.header("Sec-WebSocket-Version") != Some(WEBSOCKET_VERSION)
+    {
+        let mut response = HttpResponse::new(StatusCode::UpgradeRequired);
+        response.add_header("Sec-WebSocket-Version", WEBSOCKET_VERSION);
+        return response;
+    }
+    // The key must be a base64 encoded 16 byte nonce.
+    let nonce = base64::engine::general_purpose::STANDARD.decode(key);
+    if nonce.map(|nonce| nonce.len()) != Ok(16)
+    {
+        return HttpResponse::new(StatusCode::BadRequest);
+    }
+
+    HttpResponse::builder()
+        .status(StatusCode::SwitchingProtocols)
+        .header("Upgrade", "websocket")
+        .header("Connection", "Upgrade")
+        .header("Sec-WebSocket-Accept", &accept_key(key))
+        .build()
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use crate::http::parse_request;
+
+    /// Verify that the `accept_key()` function computes the accept key of the example in
+    /// RFC 6455, section 1.3.
+    #[test]
+    fn test_accept_key()
+    {
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    /// Verify that the `handshake()` function accepts valid upgrade requests.
+    #[test]
+    fn test_handshake()
+    {
+        let request = parse_request(
+            "GET /chats/34/live HTTP/1.1\r\n\
+             Host: server.example.com\r\n\
+             Upgrade: websocket\r\n\
+             Connection: keep-alive, Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+             Sec-WebSocket-Version: 13\r\n\r\n"
+        ).unwrap();
+
+        let response = handshake(&request);
+        let expected = b"HTTP/1.1 101 Switching Protocols\r\n\
+            Upgrade: websocket\r\n\
+            Connection: Upgrade\r\n\
+            Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+            \r\n";
+        assert_eq!(response.to_bytes(), expected.to_vec());
+    }
+
+    /// Verify that the `handshake()` function rejects invalid upgrade requests.
+    #[test]
+    fn test_handshake_invalid()
+    {
+        let head = "GET /chats/34/live HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n";
+
+        // Test that another version of the protocol requires an upgrade.
+        let mut raw_request = format!("{}Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 8\r\n\r\n", head);
+        let mut response = handshake(&parse_request(&raw_request).unwrap());
+        assert_eq!(response.status(), StatusCode::UpgradeRequired);
+        assert_eq!(response.header("Sec-WebSocket-Version"), Some("13"));
+
+        // Test that the key must be a 16 byte nonce.
+        let keys = ["", "not base64!", "c2hvcnQ="];
+        for key in keys.iter()
+        {
+            raw_request = format!("{}Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n", head, key);
+            response = handshake(&parse_request(&raw_request).unwrap());
+            assert_eq!(response.status(), StatusCode::BadRequest, "{:?}", key);
+        }
+
+        // Test that a request that isn't an upgrade is rejected.
+        raw_request = String::from("GET /chats/34/live HTTP/1.1\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n");
+        response = handshake(&parse_request(&raw_request).unwrap());
+        assert_eq!(response.status(), StatusCode::BadRequest);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,559 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use chatty::http::{self, HttpRequest, ParseOptions};
+use chatty::middleware::{Logger, Middleware};
+use chatty::response::{HttpResponse, StatusCode};
+use chatty::server::{self, Server};
+
+/// Spawn a server on a free port of the loopback interface and return its address.
+fn spawn_server<H>(workers: usize, handler: H) -> SocketAddr
+where
+    H: Fn(&chatty::http::HttpRequest) -> HttpResponse + Send + Sync + 'static,
+{
+    let server = Server::bind("127.0.0.1:0", workers).unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run(handler));
+
+    addr
+}
+
+/// Send a request over a new connection and read the response until the server closes it.
+fn round_trip(addr: SocketAddr, request: &str) -> String
+{
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(request.as_bytes()).unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    without_date(&response)
+}
+
+/// Remove the `Date` header, which changes from one response to the next, from a response.
+fn without_date(response: &str) -> String
+{
+    response.split_inclusive("\r\n").filter(|line| !line.starts_with("Date: ")).collect()
+}
+
+/// Poll a condition that becomes true in the background, e.g. once a worker is done with a
+/// connection, and return whether it became true within a few seconds.
+fn wait_until<F: Fn() -> bool>(condition: F) -> bool
+{
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition()
+    {
+        if Instant::now() >= deadline
+        {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    true
+}
+
+/// Verify that a `Server` responds to requests with the handler's response, and answers
+/// malformed requests with a `400 Bad Request` without stopping.
+#[test]
+fn test_serve()
+{
+    let addr = spawn_server(1, |request| HttpResponse::ok(request.uri().to_string_lossy().into_owned()));
+
+    // Test that the handler's response is written back.
+    let mut response = round_trip(addr, "GET /chats/34 HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert_eq!(response, "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 9\r\n\r\n/chats/34");
+
+    // Test that a malformed request is answered with a 400 and the server keeps serving.
+    response = round_trip(addr, "GET /chats/34 HTTP/2.0\r\n\r\n");
+    let body = r#"{"detail":"Unsupported HTTP version!","error":"unsupported_version"}"#;
+    assert_eq!(
+        response,
+        format!(
+            "HTTP/1.1 400 Bad Request\r\nContent-Type: application/json\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    );
+
+    response = round_trip(addr, "GET / HTTP/1.0\r\n\r\n");
+    assert_eq!(response, "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 1\r\n\r\n/");
+}
+
+/// Verify that a `Server` keeps a connection open until the client asks for it to be closed.
+#[test]
+fn test_serve_keep_alive()
+{
+    let addr = spawn_server(1, |request| HttpResponse::ok(request.uri().to_string_lossy().into_owned()));
+
+    // Test that a HTTP/1.1 connection serves requests until one asks to close it.
+    let mut response = round_trip(
+        addr,
+        "GET /chats HTTP/1.1\r\n\r\nGET /chats/34 HTTP/1.1\r\nConnection: close\r\n\r\n",
+    );
+    assert_eq!(
+        response,
+        "HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\n/chats\
+         HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 9\r\n\r\n/chats/34"
+    );
+
+    // Test that a HTTP/1.0 client asking to keep the connection open is told it will be.
+    response = round_trip(addr, "GET /chats HTTP/1.0\r\nConnection: keep-alive\r\n\r\nGET / HTTP/1.0\r\n\r\n");
+    assert_eq!(
+        response,
+        "HTTP/1.1 200 OK\r\nConnection: keep-alive\r\nContent-Length: 6\r\n\r\n/chats\
+         HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 1\r\n\r\n/"
+    );
+}
+
+/// Verify that a `Server` handles connections in parallel on its worker threads.
+#[test]
+fn test_serve_concurrently()
+{
+    let addr = spawn_server(4, |_| {
+        thread::sleep(Duration::from_millis(300));
+        HttpResponse::new(StatusCode::NoContent)
+    });
+
+    let start = Instant::now();
+    let clients: Vec<_> = (0 .. 4)
+        .map(|_| thread::spawn(move || round_trip(addr, "GET / HTTP/1.0\r\n\r\n")))
+        .collect();
+    for client in clients
+    {
+        assert_eq!(client.join().unwrap(), "HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n");
+    }
+
+    // Handled one at a time, the requests would take at least 1200ms.
+    assert!(start.elapsed() < Duration::from_millis(1000), "{:?}", start.elapsed());
+}
+
+/// Verify that a handler panicking doesn't stop a worker from handling later connections.
+#[test]
+fn test_serve_handler_panic()
+{
+    let addr = spawn_server(1, |request| {
+        if request.uri().to_str() == Some("/panic")
+        {
+            panic!("The handler panicked");
+        }
+        HttpResponse::new(StatusCode::NoContent)
+    });
+
+    // Test that the connection is closed without a response.
+    let mut response = round_trip(addr, "GET /panic HTTP/1.0\r\n\r\n");
+    assert_eq!(response, "");
+
+    response = round_trip(addr, "GET / HTTP/1.0\r\n\r\n");
+    assert_eq!(response, "HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n");
+}
+
+/// Verify that a `Server` can't be created without any workers.
+#[test]
+fn test_bind_without_workers()
+{
+    assert!(Server::bind("127.0.0.1:0", 0).is_err());
+}
+
+/// Verify that the `on_response()` callback observes every request a `Server` responds to.
+#[test]
+fn test_on_response()
+{
+    let observed = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&observed);
+
+    let server = Server::bind("127.0.0.1:0", 1).unwrap()
+        .on_response(move |request, response, elapsed| {
+            let entry = (request.to_string(), response.status(), elapsed);
+            recorded.lock().unwrap().push(entry);
+        });
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || {
+        server.run(|request| {
+            thread::sleep(Duration::from_millis(20));
+            match request.uri().to_str()
+            {
+                Some("/chats") => HttpResponse::ok("all chats"),
+                _ => HttpResponse::not_found(),
+            }
+        })
+    });
+
+    round_trip(addr, "GET /chats HTTP/1.1\r\n\r\nGET /missing HTTP/1.1\r\nConnection: close\r\n\r\n");
+    round_trip(addr, "GET /chats HTTP/1.0\r\n\r\n");
+
+    let observed = observed.lock().unwrap();
+    let summaries: Vec<(&str, StatusCode)> = observed.iter()
+        .map(|(request, status, _)| (request.as_str(), *status))
+        .collect();
+    assert_eq!(
+        summaries,
+        vec![
+            ("GET /chats HTTP/1.1", StatusCode::Ok),
+            ("GET /missing HTTP/1.1", StatusCode::NotFound),
+            ("GET /chats HTTP/1.0", StatusCode::Ok),
+        ]
+    );
+
+    // Test that the time spent in the handler is included in the timing.
+    assert!(observed.iter().all(|(_, _, elapsed)| *elapsed >= Duration::from_millis(20)));
+}
+
+/// Verify that a shut down `Server` refuses new connections, but finishes the requests it was
+/// handling first.
+#[test]
+fn test_shutdown()
+{
+    let server = Server::bind("127.0.0.1:0", 2).unwrap();
+    let addr = server.local_addr().unwrap();
+    let handle = server.shutdown_handle();
+    let running = thread::spawn(move || {
+        server.run(|_| {
+            thread::sleep(Duration::from_millis(300));
+            HttpResponse::ok("done")
+        })
+    });
+
+    // Test that an idle connection doesn't hold up the shutdown.
+    let _idle = TcpStream::connect(addr).unwrap();
+    let in_progress = thread::spawn(move || round_trip(addr, "GET /chats HTTP/1.1\r\n\r\n"));
+    thread::sleep(Duration::from_millis(100));
+
+    handle.shutdown();
+    running.join().unwrap().unwrap();
+
+    // Test that the request in progress was responded to before the connection was closed.
+    assert_eq!(
+        in_progress.join().unwrap(),
+        "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 4\r\n\r\ndone"
+    );
+    assert!(TcpStream::connect(addr).is_err());
+}
+
+/// Verify that a `Server` passes requests through its middleware before the handler.
+#[test]
+fn test_wrap()
+{
+    struct RequireAuthorization;
+
+    impl Middleware for RequireAuthorization
+    {
+        fn handle(&self, request: &HttpRequest, next: &dyn Fn(&HttpRequest) -> HttpResponse) -> HttpResponse
+        {
+            match request.header("Authorization")
+            {
+                Some(_) => next(request),
+                None => HttpResponse::new(StatusCode::Unauthorized),
+            }
+        }
+    }
+
+    let server = Server::bind("127.0.0.1:0", 1).unwrap()
+        .wrap(Logger)
+        .wrap(RequireAuthorization);
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run(|_| HttpResponse::ok("all chats")));
+
+    let mut response = round_trip(addr, "GET /chats HTTP/1.0\r\n\r\n");
+    assert_eq!(response, "HTTP/1.1 401 Unauthorized\r\nConnection: close\r\nContent-Length: 0\r\n\r\n");
+
+    response = round_trip(addr, "GET /chats HTTP/1.0\r\nAuthorization: Bearer abc\r\n\r\n");
+    assert_eq!(response, "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 9\r\n\r\nall chats");
+}
+
+/// Verify that a handler can look up the address of the client that sent a request.
+#[test]
+fn test_serve_peer_addr()
+{
+    let addr = spawn_server(1, |request| {
+        let peer_addr = request.peer_addr().map(|peer_addr| peer_addr.to_string()).unwrap_or_default();
+        HttpResponse::ok(peer_addr)
+    });
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    let local_addr = stream.local_addr().unwrap().to_string();
+    stream.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    assert_eq!(
+        without_date(&response),
+        format!("HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}", local_addr.len(), local_addr)
+    );
+}
+
+/// Verify that a `Server` tells a client that sent `Expect: 100-continue` to send the body.
+#[test]
+fn test_serve_expect_continue()
+{
+    let addr = spawn_server(1, |request| HttpResponse::ok(request.body().as_bytes().to_vec()));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"POST /messages HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 5\r\nConnection: close\r\n\r\n").unwrap();
+
+    // Test that the interim response arrives before the body is sent.
+    let mut interim = [0; 25];
+    stream.read_exact(&mut interim).unwrap();
+    assert_eq!(&interim[..], b"HTTP/1.1 100 Continue\r\n\r\n");
+
+    stream.write_all(b"Hello").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert_eq!(without_date(&response), "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 5\r\n\r\nHello");
+
+    // Test that a client that doesn't expect it only gets the final response.
+    response = round_trip(addr, "POST /messages HTTP/1.1\r\nContent-Length: 5\r\nConnection: close\r\n\r\nHello");
+    assert_eq!(response, "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 5\r\n\r\nHello");
+}
+
+/// Verify that a `Server` rejects a body that's larger than its parse options allow with a
+/// `413 Payload Too Large`, without telling a client that sent `Expect: 100-continue` to send it.
+#[test]
+fn test_serve_max_body_bytes()
+{
+    let options = ParseOptions { max_body_bytes: Some(5), ..ParseOptions::default() };
+    let server = Server::bind("127.0.0.1:0", 1).unwrap().parse_options(options);
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run(|request| HttpResponse::ok(request.body().as_bytes().to_vec())));
+
+    let response = round_trip(addr, "POST /messages HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 6\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+    assert!(!response.contains("100 Continue"));
+
+    // Test that a body within the limit is still read.
+    let response = round_trip(addr, "POST /messages HTTP/1.1\r\nContent-Length: 5\r\nConnection: close\r\n\r\nHello");
+    assert_eq!(response, "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 5\r\n\r\nHello");
+
+    // Test that the server limits bodies by default.
+    let addr = spawn_server(1, |_| HttpResponse::ok("done"));
+    let request = format!("POST /messages HTTP/1.1\r\nContent-Length: {}\r\n\r\n", server::DEFAULT_MAX_BODY_BYTES + 1);
+    assert!(round_trip(addr, &request).starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+}
+
+/// Verify that a `Server` responds to a `HEAD` request with the headers of the corresponding
+/// `GET` request but without a body.
+#[test]
+fn test_serve_head()
+{
+    let addr = spawn_server(1, |request| {
+        HttpResponse::builder()
+            .header("Content-Type", "text/plain")
+            .body(request.uri().to_string_lossy().into_owned())
+            .build()
+    });
+
+    let get = round_trip(addr, "GET /chats/34 HTTP/1.1\r\nConnection: close\r\n\r\n");
+    let head = round_trip(addr, "HEAD /chats/34 HTTP/1.1\r\nConnection: close\r\n\r\n");
+
+    let (get_head, get_body) = get.split_at(get.find("\r\n\r\n").unwrap() + 4);
+    assert_eq!(get_body, "/chats/34");
+    assert!(head.ends_with("\r\n\r\n"));
+
+    // Test that the headers are the same, including the length of the body that was left out.
+    let headers = |response: &str| {
+        let mut lines: Vec<String> = response.trim_end().lines().map(String::from).collect();
+        lines.sort();
+        lines
+    };
+    assert_eq!(headers(&head), headers(get_head));
+    assert!(head.contains("Content-Length: 9\r\n"));
+}
+
+/// Verify that a `Server` answers a client that stalls partway through a request with a `408
+/// Request Timeout`, and closes connections that stay idle.
+#[test]
+fn test_read_timeout()
+{
+    let server = Server::bind("127.0.0.1:0", 1).unwrap().read_timeout(Duration::from_millis(200));
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run(|_| HttpResponse::ok("done")));
+
+    // Test that a partial request times out.
+    let start = Instant::now();
+    let response = round_trip(addr, "GET /chats HTTP/1.1\r\nHost: ");
+    assert_eq!(response, "HTTP/1.1 408 Request Timeout\r\nConnection: close\r\nContent-Length: 0\r\n\r\n");
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    // Test that an idle connection is closed without a response, after the requests it made.
+    let start = Instant::now();
+    let response = round_trip(addr, "GET /chats HTTP/1.1\r\n\r\n");
+    assert_eq!(response, "HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\ndone");
+    assert!(start.elapsed() >= Duration::from_millis(200));
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+/// Verify that a `Server` whose workers are busy and whose queue is full turns new connections
+/// away with a `503 Service Unavailable` rather than making them wait.
+#[test]
+fn test_overload()
+{
+    let server = Server::bind("127.0.0.1:0", 1).unwrap().queue_capacity(1).retry_after(Duration::from_millis(1500));
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || {
+        server.run(|_| {
+            thread::sleep(Duration::from_millis(300));
+            HttpResponse::ok("slow")
+        })
+    });
+
+    // Test that the first connection occupies the worker and the second one waits in the queue.
+    let slow = move |delay| {
+        thread::sleep(delay);
+        round_trip(addr, "GET /chats HTTP/1.1\r\nConnection: close\r\n\r\n")
+    };
+    let first = thread::spawn(move || slow(Duration::from_millis(0)));
+    let second = thread::spawn(move || slow(Duration::from_millis(100)));
+    thread::sleep(Duration::from_millis(200));
+
+    let start = Instant::now();
+    let response = round_trip(addr, "GET /chats HTTP/1.1\r\n\r\n");
+    assert_eq!(
+        response,
+        "HTTP/1.1 503 Service Unavailable\r\nRetry-After: 2\r\nConnection: close\r\nContent-Length: 0\r\n\r\n"
+    );
+    assert!(start.elapsed() < Duration::from_millis(300));
+
+    let ok = "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 4\r\n\r\nslow";
+    assert_eq!(first.join().unwrap(), ok);
+    assert_eq!(second.join().unwrap(), ok);
+}
+
+/// Verify that the `common_log_format()` function formats requests like web servers' access
+/// logs.
+#[test]
+fn test_common_log_format()
+{
+    let mut request = http::parse_request("GET /chats?limit=20 HTTP/1.1\r\nHost: www.example.com\r\n\r\n").unwrap();
+    request.set_peer_addr("127.0.0.1:51234".parse().unwrap());
+    let time = UNIX_EPOCH + Duration::from_secs(971186136);
+    assert_eq!(
+        server::common_log_format(&request, &HttpResponse::ok("all chats"), time),
+        "127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] \"GET /chats?limit=20 HTTP/1.1\" 200 9"
+    );
+
+    // Test that a missing address and an empty body are written as dashes.
+    request = http::parse_request("DELETE /chats/34 HTTP/1.0\r\n\r\n").unwrap();
+    assert_eq!(
+        server::common_log_format(&request, &HttpResponse::new(StatusCode::NotFound), UNIX_EPOCH),
+        "- - - [01/Jan/1970:00:00:00 +0000] \"DELETE /chats/34 HTTP/1.0\" 404 -"
+    );
+
+    // Test that a server can log the requests it handles.
+    let lines = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&lines);
+    let server = Server::bind("127.0.0.1:0", 1).unwrap()
+        .on_response(move |request, response, _| {
+            recorded.lock().unwrap().push(server::common_log_format(request, response, SystemTime::now()));
+        });
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run(|_| HttpResponse::ok("all chats")));
+
+    round_trip(addr, "GET /chats HTTP/1.1\r\nConnection: close\r\n\r\n");
+    let lines = lines.lock().unwrap();
+    assert!(lines[0].starts_with("127.0.0.1 - - ["), "{}", lines[0]);
+    assert!(lines[0].ends_with(" +0000] \"GET /chats HTTP/1.1\" 200 9"), "{}", lines[0]);
+}
+
+/// Verify that a `Server` sends the date of every response in the `Date` header, and
+/// identifies itself in the `Server` header when it has a name.
+#[test]
+fn test_serve_date_and_server_headers()
+{
+    let server = Server::bind("127.0.0.1:0", 1).unwrap().server_name("chatty/0.1");
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run(|_: &HttpRequest| HttpResponse::ok("Hello")));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    // Test that the date is a valid HTTP date close to the current time.
+    let date = response.lines().find_map(|line| line.strip_prefix("Date: ")).unwrap();
+    let sent_at = httpdate::parse_http_date(date).unwrap();
+    let now = SystemTime::now();
+    assert!(sent_at <= now && now.duration_since(sent_at).unwrap() < Duration::from_secs(60));
+    assert!(response.contains("\r\nServer: chatty/0.1\r\n"));
+
+    // Test that a server without a name doesn't send a `Server` header.
+    let addr = spawn_server(1, |_| HttpResponse::ok("Hello"));
+    let response = round_trip(addr, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert_eq!(response, "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 5\r\n\r\nHello");
+}
+
+/// Verify that a `Server` counts the connections it accepts and the responses it sends.
+#[test]
+fn test_serve_stats()
+{
+    let server = Server::bind("127.0.0.1:0", 2).unwrap();
+    let addr = server.local_addr().unwrap();
+    let stats = server.stats();
+    thread::spawn(move || {
+        server.run(|request: &HttpRequest| match request.uri().to_str()
+        {
+            Some("/missing") => HttpResponse::not_found(),
+            Some("/error") => HttpResponse::new(StatusCode::InternalServerError),
+            _ => HttpResponse::ok("Hello"),
+        })
+    });
+
+    round_trip(addr, "GET / HTTP/1.0\r\n\r\n");
+    round_trip(addr, "GET /chats HTTP/1.1\r\n\r\nGET /missing HTTP/1.1\r\nConnection: close\r\n\r\n");
+    round_trip(addr, "GET /error HTTP/1.0\r\n\r\n");
+    round_trip(addr, "NOT A REQUEST\r\n\r\n");
+    assert_eq!(stats.accepted(), 4);
+    assert_eq!(stats.requests(), 5);
+    assert_eq!(stats.client_errors(), 2);
+    assert_eq!(stats.server_errors(), 1);
+
+    // Test that a connection is active while it's open, and isn't once it's closed.
+    assert!(wait_until(|| stats.active() == 0));
+    let stream = TcpStream::connect(addr).unwrap();
+    assert!(wait_until(|| stats.active() == 1));
+    assert_eq!(stats.accepted(), 5);
+
+    drop(stream);
+    assert!(wait_until(|| stats.active() == 0));
+}
+
+/// Verify that a `Server` that assigns request ids reuses a client's `X-Request-Id`, generates
+/// one otherwise, and echoes the id in the response.
+#[test]
+fn test_serve_request_ids()
+{
+    let server = Server::bind("127.0.0.1:0", 1).unwrap().request_ids(true);
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || server.run(|request: &HttpRequest| HttpResponse::ok(request.request_id().unwrap_or_default())));
+
+    let mut response = round_trip(addr, "GET / HTTP/1.1\r\nX-Request-Id: f0e1-d2c3/b4a5\r\nConnection: close\r\n\r\n");
+    assert_eq!(
+        response,
+        "HTTP/1.1 200 OK\r\nX-Request-Id: f0e1-d2c3/b4a5\r\nConnection: close\r\nContent-Length: 14\r\n\r\nf0e1-d2c3/b4a5"
+    );
+
+    // Test that a request without an id, or with one that can't be used, gets a fresh UUID.
+    let requests = [
+        String::from("GET / HTTP/1.1\r\nConnection: close\r\n\r\n"),
+        String::from("GET / HTTP/1.1\r\nX-Request-Id: two words\r\nConnection: close\r\n\r\n"),
+        format!("GET / HTTP/1.1\r\nX-Request-Id: {}\r\nConnection: close\r\n\r\n", "a".repeat(129)),
+    ];
+    let mut generated = Vec::new();
+    for request in requests.iter()
+    {
+        response = round_trip(addr, request);
+        let request_id = response.lines().find_map(|line| line.strip_prefix("X-Request-Id: ")).unwrap();
+        assert_eq!(uuid::Uuid::parse_str(request_id).unwrap().get_version_num(), 4);
+        assert!(response.ends_with(&format!("\r\n\r\n{}", request_id)));
+        generated.push(String::from(request_id));
+    }
+    generated.sort();
+    generated.dedup();
+    assert_eq!(generated.len(), 3);
+
+    // Test that a server that doesn't assign ids leaves requests and responses alone.
+    let addr = spawn_server(1, |request| HttpResponse::ok(request.request_id().unwrap_or("none")));
+    response = round_trip(addr, "GET / HTTP/1.1\r\nX-Request-Id: f0e1\r\nConnection: close\r\n\r\n");
+    assert_eq!(response, "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 4\r\n\r\nnone");
+}
\ No newline at end of file